@@ -0,0 +1,550 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_rpc_client_api::client_error::Error as RpcError;
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use spl_token::amount_to_ui_amount_string_trimmed;
+use theforgeonsolana::{
+    client::{decode_instruction_error, Forge},
+    constants::{PAUSE_SMELT, PAUSE_UNSMELT},
+    instruction::{self, ConfigParam, ForgeMints},
+    math,
+    state::SmeltingState,
+};
+
+type CliResult<T> = Result<T, Box<dyn Error>>;
+
+// Nonces tried before giving up on a smelt proof
+const MAX_NONCE_ATTEMPTS: u64 = 1 << 32;
+
+#[derive(Parser)]
+#[command(name = "forge", about = "Operate a forge deployment", version)]
+struct Cli {
+    /// RPC endpoint, defaults to a local test validator
+    #[arg(
+        long,
+        short,
+        global = true,
+        env = "FORGE_URL",
+        default_value = "http://127.0.0.1:8899"
+    )]
+    url: String,
+
+    /// Keypair that signs and pays for transactions
+    #[arg(long, short, global = true, env = "FORGE_KEYPAIR")]
+    keypair: Option<PathBuf>,
+
+    /// Forge program id
+    #[arg(long, global = true, env = "FORGE_PROGRAM_ID", default_value_t = theforgeonsolana::id())]
+    program_id: Pubkey,
+
+    #[arg(long, global = true, value_enum, default_value_t = Output::Display)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Display,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create the forge state, INGOT mint and ORE vault, the keypair must be the
+    /// program's upgrade authority
+    Init {
+        #[arg(long)]
+        ore_mint: Pubkey,
+        #[arg(long)]
+        coal_mint: Pubkey,
+        #[arg(long, default_value_t = 9)]
+        ingot_decimals: u8,
+        /// Create INGOT under Token-2022 instead of SPL Token
+        #[arg(long)]
+        ingot_token_2022: bool,
+        /// INGOT per ORE, as numerator:denominator
+        #[arg(long, default_value = "1:1", value_parser = parse_ratio)]
+        wrap_ratio: (u64, u64),
+    },
+    /// Show the decoded forge state
+    Show,
+    /// Mine a proof and smelt ORE into INGOT from the keypair's token accounts
+    Smelt {
+        /// ORE amount, e.g. 1.5
+        amount: String,
+        /// Fail unless at least this much INGOT is minted
+        #[arg(long)]
+        min_out: Option<String>,
+        /// Also record the smelt in the keypair's stats account
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Burn INGOT for ORE, less the unsmelt fee
+    Unsmelt {
+        /// INGOT amount, e.g. 1.5
+        amount: String,
+        /// Fail unless at least this much ORE is returned
+        #[arg(long)]
+        min_out: Option<String>,
+        #[arg(long)]
+        stats: bool,
+    },
+    /// Withdraw accrued unsmelt fees as the authority or treasury
+    WithdrawFees {
+        /// ORE amount, e.g. 1.5
+        amount: String,
+        /// ORE token account to pay out to, defaults to the keypair's
+        #[arg(long)]
+        destination: Option<Pubkey>,
+    },
+    /// Authority-only configuration changes
+    #[command(subcommand)]
+    Admin(AdminCommand),
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    SetDifficulty {
+        difficulty: u8,
+    },
+    /// COAL amounts, e.g. 0.5
+    SetCoalCost {
+        coal_per_ore: String,
+        #[arg(default_value = "0")]
+        coal_base_cost: String,
+    },
+    SetSuccessRate {
+        percent: u8,
+    },
+    SetUnsmeltFee {
+        bps: u16,
+    },
+    /// INGOT amount
+    SetMaxIngotSupply {
+        amount: String,
+    },
+    /// Largest amount in base units a single instruction may move
+    SetMaxAmount {
+        amount: u64,
+    },
+    SetWrapRatio {
+        #[arg(value_parser = parse_ratio)]
+        ratio: (u64, u64),
+    },
+    SetTreasury {
+        treasury: Pubkey,
+    },
+    /// Name, symbol and URI wallets show for INGOT
+    SetMetadata {
+        name: String,
+        symbol: String,
+        uri: String,
+    },
+    ProposeAuthority {
+        new_authority: Pubkey,
+    },
+    /// Sign as the proposed authority to take over the forge
+    AcceptAuthority,
+    /// Rewrite the forge state in the current layout after a program upgrade
+    MigrateState,
+    /// Pause operations; pass none to unpause everything
+    Pause {
+        #[arg(long)]
+        smelt: bool,
+        #[arg(long)]
+        unsmelt: bool,
+    },
+}
+
+fn parse_ratio(ratio: &str) -> Result<(u64, u64), String> {
+    let (numerator, denominator) = ratio
+        .split_once(':')
+        .ok_or_else(|| format!("expected numerator:denominator, got {ratio}"))?;
+    let parse = |value: &str| value.trim().parse::<u64>().map_err(|err| err.to_string());
+    Ok((parse(numerator)?, parse(denominator)?))
+}
+
+// Exact decimal parsing, floats would round large base-unit amounts
+fn parse_amount(amount: &str, decimals: u8) -> CliResult<u64> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if fraction.len() > decimals as usize {
+        return Err(format!("{amount} has more than {decimals} decimals").into());
+    }
+    let digits = format!("{whole}{fraction:0<width$}", width = decimals as usize);
+    Ok(digits.parse()?)
+}
+
+fn ui(amount: u64, decimals: u8) -> String {
+    amount_to_ui_amount_string_trimmed(amount, decimals)
+}
+
+struct Context {
+    rpc: RpcClient,
+    payer: Keypair,
+    forge: Forge,
+    output: Output,
+}
+
+impl Context {
+    fn state(&self) -> CliResult<SmeltingState> {
+        Ok(self.forge.fetch_state(&self.rpc)?)
+    }
+
+    fn send(&self, instructions: &[Instruction]) -> CliResult<Value> {
+        let blockhash = self.rpc.get_latest_blockhash()?;
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.payer.pubkey()),
+            &[&self.payer],
+            blockhash,
+        );
+        let signature = self
+            .rpc
+            .send_and_confirm_transaction(&transaction)
+            .map_err(|err| self.describe_error(instructions, err))?;
+        Ok(json!({ "signature": signature.to_string() }))
+    }
+
+    // Spells out custom codes returned by forge instructions
+    fn describe_error(&self, instructions: &[Instruction], err: RpcError) -> Box<dyn Error> {
+        if let Some(TransactionError::InstructionError(index, error)) = err.get_transaction_error()
+        {
+            let forge_error = instructions
+                .get(index as usize)
+                .filter(|instruction| instruction.program_id == self.forge.program_id)
+                .and_then(|_| decode_instruction_error(&error));
+            if let Some(forge_error) = forge_error {
+                return format!("{forge_error} (forge error {})", forge_error as u32).into();
+            }
+        }
+        err.into()
+    }
+
+    fn token_account(&self, mint: &Pubkey, token_program: &Pubkey) -> (Pubkey, Instruction) {
+        let owner = self.payer.pubkey();
+        (
+            get_associated_token_address_with_program_id(&owner, mint, token_program),
+            create_associated_token_account_idempotent(&owner, &owner, mint, token_program),
+        )
+    }
+
+    fn print(&self, value: &Value) {
+        match self.output {
+            Output::Json => println!("{value}"),
+            Output::Display => {
+                if let Value::Object(fields) = value {
+                    for (key, value) in fields {
+                        match value {
+                            Value::String(value) => println!("{key}: {value}"),
+                            value => println!("{key}: {value}"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn show(state: &SmeltingState, forge: &Forge) -> Value {
+    json!({
+        "program_id": forge.program_id.to_string(),
+        "state": forge.state.to_string(),
+        "authority": state.authority.to_string(),
+        "pending_authority": state.pending_authority.to_string(),
+        "treasury": state.treasury.to_string(),
+        "ore_mint": state.ore_mint.to_string(),
+        "coal_mint": state.coal_mint.to_string(),
+        "ingot_mint": state.ingot_mint.to_string(),
+        "ore_vault": state.ore_vault.to_string(),
+        "ore_token_program": state.ore_token_program.to_string(),
+        "coal_token_program": state.coal_token_program.to_string(),
+        "ingot_token_program": state.ingot_token_program.to_string(),
+        "total_ingots_minted": ui(state.total_ingots_minted, state.ingot_decimals),
+        "max_ingot_supply": ui(state.max_ingot_supply, state.ingot_decimals),
+        "total_ore_locked": ui(state.total_ore_locked, state.ore_decimals),
+        "total_fees_accrued": ui(state.total_fees_accrued, state.ore_decimals),
+        "wrap_ratio": format!("{}:{}", state.wrap_ratio_numerator, state.wrap_ratio_denominator),
+        "difficulty": state.difficulty,
+        "success_rate": state.success_rate,
+        "unsmelt_fee_bps": state.unsmelt_fee_bps,
+        "coal_per_ore": ui(state.coal_per_ore, state.coal_decimals),
+        "coal_base_cost": ui(state.coal_base_cost, state.coal_decimals),
+        "max_amount": state.max_amount,
+        "paused": {
+            "smelt": state.is_paused(PAUSE_SMELT),
+            "unsmelt": state.is_paused(PAUSE_UNSMELT),
+        },
+    })
+}
+
+fn run(context: &Context, command: Command) -> CliResult<Value> {
+    let program_id = context.forge.program_id;
+    let payer = context.payer.pubkey();
+
+    match command {
+        Command::Init {
+            ore_mint,
+            coal_mint,
+            ingot_decimals,
+            ingot_token_2022,
+            wrap_ratio: (numerator, denominator),
+        } => {
+            let ore_token_program = context.rpc.get_account(&ore_mint)?.owner;
+            let ingot_token_program = if ingot_token_2022 {
+                spl_token_2022::id()
+            } else {
+                spl_token::id()
+            };
+            let mut result = context.send(&[instruction::initialize(
+                &program_id,
+                &payer,
+                &ore_mint,
+                &coal_mint,
+                &ore_token_program,
+                &ingot_token_program,
+                ingot_decimals,
+                numerator,
+                denominator,
+            )])?;
+            result["ingot_mint"] = json!(context.forge.ingot_mint.to_string());
+            Ok(result)
+        }
+        Command::Show => Ok(show(&context.state()?, &context.forge)),
+        Command::Smelt {
+            amount,
+            min_out,
+            stats,
+        } => {
+            let state = context.state()?;
+            let amount = parse_amount(&amount, state.ore_decimals)?;
+            let min_out = match min_out {
+                Some(min_out) => parse_amount(&min_out, state.ingot_decimals)?,
+                None => 0,
+            };
+            let (ingot_amount, ore_used) = state.ore_to_ingot(amount)?;
+            let coal_amount = state.coal_cost(ore_used)?;
+            let nonce = Forge::find_nonce(&state, &payer, 0, MAX_NONCE_ATTEMPTS)
+                .ok_or("no nonce meets the current difficulty")?;
+
+            let (ingot_account, create_ingot_account) =
+                context.token_account(&state.ingot_mint, &state.ingot_token_program);
+            let mut smelt = context.forge.smelt(
+                &state,
+                &payer,
+                &get_associated_token_address_with_program_id(
+                    &payer,
+                    &state.ore_mint,
+                    &state.ore_token_program,
+                ),
+                &get_associated_token_address_with_program_id(
+                    &payer,
+                    &state.coal_mint,
+                    &state.coal_token_program,
+                ),
+                &ingot_account,
+                amount,
+                nonce,
+                min_out,
+            );
+            if stats {
+                smelt = context.forge.with_user_stats(smelt, &payer);
+            }
+            let mut result = context.send(&[create_ingot_account, smelt])?;
+            result["ore"] = json!(ui(ore_used, state.ore_decimals));
+            result["ingot"] = json!(ui(ingot_amount, state.ingot_decimals));
+            result["coal_burned"] = json!(ui(coal_amount, state.coal_decimals));
+            Ok(result)
+        }
+        Command::Unsmelt {
+            amount,
+            min_out,
+            stats,
+        } => {
+            let state = context.state()?;
+            let amount = parse_amount(&amount, state.ingot_decimals)?;
+            let min_out = match min_out {
+                Some(min_out) => parse_amount(&min_out, state.ore_decimals)?,
+                None => 0,
+            };
+            let ore_amount = state.ingot_to_ore(amount)?;
+            let fee = state.calculate_unsmelt_fee(ore_amount)?;
+            let ore_out = math::sub(ore_amount, fee)?;
+
+            let (ore_account, create_ore_account) =
+                context.token_account(&state.ore_mint, &state.ore_token_program);
+            let mut unsmelt = context.forge.unsmelt(
+                &state,
+                &payer,
+                &ore_account,
+                &get_associated_token_address_with_program_id(
+                    &payer,
+                    &state.ingot_mint,
+                    &state.ingot_token_program,
+                ),
+                amount,
+                min_out,
+            );
+            if stats {
+                unsmelt = context.forge.with_user_stats(unsmelt, &payer);
+            }
+            let mut result = context.send(&[create_ore_account, unsmelt])?;
+            result["ore"] = json!(ui(ore_out, state.ore_decimals));
+            result["fee"] = json!(ui(fee, state.ore_decimals));
+            Ok(result)
+        }
+        Command::WithdrawFees {
+            amount,
+            destination,
+        } => {
+            let state = context.state()?;
+            let amount = parse_amount(&amount, state.ore_decimals)?;
+            let mut instructions = vec![];
+            let destination = match destination {
+                Some(destination) => destination,
+                None => {
+                    let (ore_account, create_ore_account) =
+                        context.token_account(&state.ore_mint, &state.ore_token_program);
+                    instructions.push(create_ore_account);
+                    ore_account
+                }
+            };
+            instructions.push(instruction::withdraw_fees(
+                &program_id,
+                &payer,
+                &destination,
+                &ForgeMints::from(&state),
+                amount,
+            ));
+
+            context.send(&instructions)
+        }
+        Command::Admin(command) => {
+            let instruction = match command {
+                AdminCommand::SetDifficulty { difficulty } => instruction::update_config(
+                    &program_id,
+                    &payer,
+                    ConfigParam::Difficulty(difficulty),
+                ),
+                AdminCommand::SetCoalCost {
+                    coal_per_ore,
+                    coal_base_cost,
+                } => {
+                    let state = context.state()?;
+                    instruction::update_config(
+                        &program_id,
+                        &payer,
+                        ConfigParam::CoalCost {
+                            coal_per_ore: parse_amount(&coal_per_ore, state.coal_decimals)?,
+                            coal_base_cost: parse_amount(&coal_base_cost, state.coal_decimals)?,
+                        },
+                    )
+                }
+                AdminCommand::SetSuccessRate { percent } => instruction::update_config(
+                    &program_id,
+                    &payer,
+                    ConfigParam::SuccessRate(percent),
+                ),
+                AdminCommand::SetUnsmeltFee { bps } => {
+                    instruction::update_config(&program_id, &payer, ConfigParam::UnsmeltFeeBps(bps))
+                }
+                AdminCommand::SetMaxIngotSupply { amount } => {
+                    let state = context.state()?;
+                    instruction::update_config(
+                        &program_id,
+                        &payer,
+                        ConfigParam::MaxIngotSupply(parse_amount(&amount, state.ingot_decimals)?),
+                    )
+                }
+                AdminCommand::SetMaxAmount { amount } => {
+                    instruction::update_config(&program_id, &payer, ConfigParam::MaxAmount(amount))
+                }
+                AdminCommand::SetWrapRatio {
+                    ratio: (numerator, denominator),
+                } => instruction::update_config(
+                    &program_id,
+                    &payer,
+                    ConfigParam::WrapRatio {
+                        numerator,
+                        denominator,
+                    },
+                ),
+                AdminCommand::SetTreasury { treasury } => {
+                    instruction::update_config(&program_id, &payer, ConfigParam::Treasury(treasury))
+                }
+                AdminCommand::SetMetadata { name, symbol, uri } => {
+                    let state = context.state()?;
+                    instruction::set_ingot_metadata(
+                        &program_id,
+                        &payer,
+                        &ForgeMints::from(&state),
+                        name,
+                        symbol,
+                        uri,
+                    )
+                }
+                AdminCommand::ProposeAuthority { new_authority } => {
+                    instruction::propose_authority(&program_id, &payer, &new_authority)
+                }
+                AdminCommand::AcceptAuthority => instruction::accept_authority(&program_id, &payer),
+                AdminCommand::MigrateState => instruction::migrate_state(&program_id, &payer),
+
+                AdminCommand::Pause { smelt, unsmelt } => {
+                    let paused_operations =
+                        (smelt as u8 * PAUSE_SMELT) | (unsmelt as u8 * PAUSE_UNSMELT);
+                    instruction::set_pause(&program_id, &payer, paused_operations)
+                }
+            };
+            context.send(&[instruction])
+        }
+    }
+}
+
+fn default_keypair_path() -> CliResult<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or("HOME is not set, pass --keypair")?;
+    Ok(PathBuf::from(home).join(".config/solana/id.json"))
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let output = cli.output;
+
+    let result = (|| {
+        let keypair_path = match cli.keypair {
+            Some(path) => path,
+            None => default_keypair_path()?,
+        };
+        let context = Context {
+            rpc: RpcClient::new_with_commitment(cli.url, CommitmentConfig::confirmed()),
+            payer: read_keypair_file(&keypair_path)
+                .map_err(|err| format!("reading {}: {err}", keypair_path.display()))?,
+            forge: Forge::new(cli.program_id),
+            output,
+        };
+        let value = run(&context, cli.command)?;
+        context.print(&value);
+        Ok::<_, Box<dyn Error>>(())
+    })();
+
+    if let Err(err) = result {
+        match output {
+            Output::Json => println!("{}", json!({ "error": err.to_string() })),
+            Output::Display => eprintln!("error: {err}"),
+        }
+        std::process::exit(1);
+    }
+}
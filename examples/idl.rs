@@ -0,0 +1,6 @@
+//! Prints the forge IDL, refresh the checked-in copy with
+//! `cargo run --example idl --features idl > idl/theforgeonsolana.json`.
+
+fn main() {
+    print!("{}", theforgeonsolana::idl::idl_json());
+}
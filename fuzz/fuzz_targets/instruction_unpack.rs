@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use theforgeonsolana::instruction::SmeltingInstruction;
+
+fuzz_target!(|data: &[u8]| {
+    // Anything that parses must encode back to exactly the bytes it came from
+    if let Ok(instruction) = SmeltingInstruction::unpack(data) {
+        assert_eq!(instruction.pack(), data);
+    }
+});
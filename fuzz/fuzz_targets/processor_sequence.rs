@@ -0,0 +1,409 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use solana_program::{
+    bpf_loader_upgradeable, program_pack::Pack, pubkey::Pubkey, system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_token::state::{Account as TokenAccount, Mint};
+use theforgeonsolana::{
+    constants::PAUSE_ALL,
+    instruction::{self, ConfigParam, ForgeMints},
+    processor::Processor,
+    state::{find_ingot_mint_address, find_smelting_state_address, SmeltingState},
+};
+
+const USERS: usize = 2;
+const DECIMALS: u8 = 6;
+const STARTING_BALANCE: u64 = 1_000_000_000;
+const PROGRAM_DATA_LAMPORTS: u64 = 1_000_000_000;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Smelt { user: u8, amount: u64 },
+    Unsmelt { user: u8, amount: u64 },
+    TransferOre { from: u8, to: u8, amount: u64 },
+    TransferIngot { from: u8, to: u8, amount: u64 },
+    SetUnsmeltFee { bps: u16 },
+    SetWrapRatio { numerator: u64, denominator: u64 },
+    SetPause { paused_operations: u8 },
+    WithdrawFees { amount: u64 },
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    wrap_ratio_numerator: u64,
+    wrap_ratio_denominator: u64,
+    ops: Vec<Op>,
+}
+
+struct User {
+    keypair: Keypair,
+    ore: Pubkey,
+    coal: Pubkey,
+    ingot: Pubkey,
+}
+
+struct Forge {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    users: Vec<User>,
+    treasury: Pubkey,
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> bool {
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        context.last_blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_ok()
+}
+
+async fn create_account(context: &mut ProgramTestContext, len: usize) -> Keypair {
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let payer = context.payer.insecure_clone();
+    let instruction = system_instruction::create_account(
+        &payer.pubkey(),
+        &account.pubkey(),
+        rent.minimum_balance(len),
+        len as u64,
+        &spl_token::id(),
+    );
+    assert!(process(context, &[instruction], &[&account]).await);
+    account
+}
+
+async fn create_mint(context: &mut ProgramTestContext, authority: &Pubkey) -> Pubkey {
+    let mint = create_account(context, Mint::LEN).await.pubkey();
+    let instruction = spl_token::instruction::initialize_mint2(
+        &spl_token::id(),
+        &mint,
+        authority,
+        None,
+        DECIMALS,
+    )
+    .unwrap();
+    assert!(process(context, &[instruction], &[]).await);
+    mint
+}
+
+async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    mint_authority: &Keypair,
+    amount: u64,
+) -> Pubkey {
+    let account = create_account(context, TokenAccount::LEN).await.pubkey();
+    let mut instructions =
+        vec![
+            spl_token::instruction::initialize_account3(&spl_token::id(), &account, mint, owner)
+                .unwrap(),
+        ];
+    if amount > 0 {
+        instructions.push(
+            spl_token::instruction::mint_to(
+                &spl_token::id(),
+                mint,
+                &account,
+                &mint_authority.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    assert!(process(context, &instructions, &[mint_authority]).await);
+    account
+}
+
+// The ProgramData account of an upgradeable deploy, which Initialize reads the upgrade
+// authority from
+async fn set_program_data(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    upgrade_authority: &Pubkey,
+) {
+    let mut data = vec![0; 45];
+    data[..4].copy_from_slice(&3u32.to_le_bytes());
+    data[12] = 1;
+    data[13..].copy_from_slice(upgrade_authority.as_ref());
+    let program_data = Account {
+        lamports: PROGRAM_DATA_LAMPORTS,
+        data,
+        owner: bpf_loader_upgradeable::id(),
+        ..Account::default()
+    };
+    context.set_account(
+        &bpf_loader_upgradeable::get_program_data_address(program_id),
+        &AccountSharedData::from(program_data),
+    );
+
+    // Taken from the payer, or the bank's capitalization no longer adds up once a slot is warped
+    let payer = context.payer.pubkey();
+    let mut payer_account = context
+        .banks_client
+        .get_account(payer)
+        .await
+        .unwrap()
+        .unwrap();
+    payer_account.lamports -= PROGRAM_DATA_LAMPORTS;
+    context.set_account(&payer, &AccountSharedData::from(payer_account));
+}
+
+async fn setup(wrap_ratio_numerator: u64, wrap_ratio_denominator: u64) -> Option<Forge> {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new(
+        "theforgeonsolana",
+        program_id,
+        processor!(Processor::process),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    let mint_authority = Keypair::new();
+    let ore_mint = create_mint(&mut context, &mint_authority.pubkey()).await;
+    let coal_mint = create_mint(&mut context, &mint_authority.pubkey()).await;
+
+    let payer = context.payer.insecure_clone();
+    set_program_data(&mut context, &program_id, &payer.pubkey()).await;
+    let initialize = instruction::initialize(
+        &program_id,
+        &payer.pubkey(),
+        &ore_mint,
+        &coal_mint,
+        &spl_token::id(),
+        &spl_token::id(),
+        DECIMALS,
+        wrap_ratio_numerator,
+        wrap_ratio_denominator,
+    );
+    if !process(&mut context, &[initialize], &[]).await {
+        return None;
+    }
+
+    let (ingot_mint, _) = find_ingot_mint_address(&program_id);
+    let mut users = Vec::with_capacity(USERS);
+    for _ in 0..USERS {
+        let keypair = Keypair::new();
+        let owner = keypair.pubkey();
+        users.push(User {
+            ore: create_token_account(
+                &mut context,
+                &ore_mint,
+                &owner,
+                &mint_authority,
+                STARTING_BALANCE,
+            )
+            .await,
+            coal: create_token_account(
+                &mut context,
+                &coal_mint,
+                &owner,
+                &mint_authority,
+                STARTING_BALANCE,
+            )
+            .await,
+            ingot: create_token_account(&mut context, &ingot_mint, &owner, &mint_authority, 0)
+                .await,
+            keypair,
+        });
+    }
+    let treasury =
+        create_token_account(&mut context, &ore_mint, &payer.pubkey(), &mint_authority, 0).await;
+
+    Some(Forge {
+        context,
+        program_id,
+        users,
+        treasury,
+    })
+}
+
+async fn read_state(forge: &mut Forge) -> SmeltingState {
+    let (state, _) = find_smelting_state_address(&forge.program_id);
+    let account = forge.context.banks_client.get_account(state).await.unwrap();
+    SmeltingState::unpack(&account.unwrap().data).unwrap()
+}
+
+async fn token_balance(forge: &mut Forge, account: Pubkey) -> u64 {
+    let account = forge
+        .context
+        .banks_client
+        .get_account(account)
+        .await
+        .unwrap();
+    TokenAccount::unpack(&account.unwrap().data).unwrap().amount
+}
+
+async fn apply(forge: &mut Forge, op: &Op) {
+    let state = read_state(forge).await;
+    let mints = ForgeMints::from(&state);
+    let program_id = forge.program_id;
+    let authority = forge.context.payer.pubkey();
+    let user = |index: &u8| &forge.users[*index as usize % USERS];
+
+    match op {
+        Op::Smelt {
+            user: index,
+            amount,
+        } => {
+            let user = user(index);
+            let nonce = (0..)
+                .find(|nonce| {
+                    SmeltingState::meets_difficulty(
+                        &state.proof_hash(&user.keypair.pubkey(), *nonce),
+                        state.difficulty,
+                    )
+                })
+                .unwrap();
+            let instruction = instruction::smelt(
+                &program_id,
+                &user.keypair.pubkey(),
+                &user.ore,
+                &user.coal,
+                &user.ingot,
+                &mints,
+                *amount,
+                nonce,
+                0,
+            );
+            let keypair = user.keypair.insecure_clone();
+            process(&mut forge.context, &[instruction], &[&keypair]).await;
+        }
+        Op::Unsmelt {
+            user: index,
+            amount,
+        } => {
+            let user = user(index);
+            let instruction = instruction::unsmelt(
+                &program_id,
+                &user.keypair.pubkey(),
+                &user.ore,
+                &user.ingot,
+                &mints,
+                *amount,
+                0,
+            );
+            let keypair = user.keypair.insecure_clone();
+            process(&mut forge.context, &[instruction], &[&keypair]).await;
+        }
+        Op::TransferOre { from, to, amount } => {
+            let (from, to) = (user(from), user(to));
+            let instruction = instruction::transfer_ore(
+                &program_id,
+                &from.ore,
+                &to.ore,
+                &from.keypair.pubkey(),
+                &mints,
+                *amount,
+            );
+            let keypair = from.keypair.insecure_clone();
+            process(&mut forge.context, &[instruction], &[&keypair]).await;
+        }
+        Op::TransferIngot { from, to, amount } => {
+            let (from, to) = (user(from), user(to));
+            let instruction = instruction::transfer_ingot(
+                &program_id,
+                &from.ingot,
+                &to.ingot,
+                &from.keypair.pubkey(),
+                &mints,
+                *amount,
+            );
+            let keypair = from.keypair.insecure_clone();
+            process(&mut forge.context, &[instruction], &[&keypair]).await;
+        }
+        Op::SetUnsmeltFee { bps } => {
+            let instruction = instruction::update_config(
+                &program_id,
+                &authority,
+                ConfigParam::UnsmeltFeeBps(*bps),
+            );
+            process(&mut forge.context, &[instruction], &[]).await;
+        }
+        Op::SetWrapRatio {
+            numerator,
+            denominator,
+        } => {
+            let instruction = instruction::update_config(
+                &program_id,
+                &authority,
+                ConfigParam::WrapRatio {
+                    numerator: *numerator,
+                    denominator: *denominator,
+                },
+            );
+            process(&mut forge.context, &[instruction], &[]).await;
+        }
+        Op::SetPause { paused_operations } => {
+            let instruction =
+                instruction::set_pause(&program_id, &authority, paused_operations & PAUSE_ALL);
+            process(&mut forge.context, &[instruction], &[]).await;
+        }
+        Op::WithdrawFees { amount } => {
+            let instruction = instruction::withdraw_fees(
+                &program_id,
+                &authority,
+                &forge.treasury,
+                &mints,
+                *amount,
+            );
+
+            process(&mut forge.context, &[instruction], &[]).await;
+        }
+    }
+}
+
+async fn check_invariants(forge: &mut Forge) {
+    let state = read_state(forge).await;
+    let vault = token_balance(forge, state.ore_vault).await;
+    let ingot_mint = forge
+        .context
+        .banks_client
+        .get_account(state.ingot_mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let ingot_supply = Mint::unpack(&ingot_mint.data).unwrap().supply;
+
+    assert_eq!(ingot_supply, state.total_ingots_minted);
+    assert_eq!(vault, state.total_ore_locked + state.total_fees_accrued);
+    assert!(state.ingot_to_ore(state.total_ingots_minted).unwrap() <= state.total_ore_locked);
+}
+
+fuzz_target!(|input: Input| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let Some(mut forge) = setup(input.wrap_ratio_numerator, input.wrap_ratio_denominator).await
+        else {
+            return;
+        };
+        for op in input.ops.iter().take(32) {
+            apply(&mut forge, op).await;
+            check_invariants(&mut forge).await;
+        }
+    });
+});
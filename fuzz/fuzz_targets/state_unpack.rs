@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use solana_program::program_pack::Pack;
+use theforgeonsolana::state::{PendingSmelt, SmeltingState};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(state) = SmeltingState::unpack_from_slice(data) {
+        let mut packed = vec![0u8; SmeltingState::LEN];
+        state.pack_into_slice(&mut packed);
+        assert_eq!(SmeltingState::unpack_from_slice(&packed).unwrap(), state);
+    }
+    let _ = SmeltingState::unpack(data);
+
+    if let Ok(pending) = PendingSmelt::unpack_from_slice(data) {
+        let mut packed = vec![0u8; PendingSmelt::LEN];
+        pending.pack_into_slice(&mut packed);
+        assert_eq!(PendingSmelt::unpack_from_slice(&packed).unwrap(), pending);
+    }
+    let _ = PendingSmelt::unpack(data);
+});
@@ -0,0 +1,23 @@
+#!/usr/bin/env bash
+# Builds the forge as it was before SmeltingState was read in place, the last revision
+# that unpacked and repacked it, into target/deploy/theforgeonsolana_pack.so. `cargo
+# test-sbf` smelts through it to measure what zero-copy saves.
+set -euo pipefail
+
+PACK_REVISION=b259a5a
+
+root=$(git rev-parse --show-toplevel)
+worktree=$(mktemp -d)
+git -C "$root" worktree add --detach "$worktree" "$PACK_REVISION"
+trap 'git -C "$root" worktree remove --force "$worktree"' EXIT
+
+# That revision shipped a program ID that isn't valid base58 and unused Anchor
+# dependencies that no longer resolve next to solana-program 2.0
+sed -i 's/declare_id!(".*")/declare_id!("Sme1tingProgram1111111111111111111111111111")/' \
+    "$worktree/src/lib.rs"
+sed -i '/^anchor-/d' "$worktree/Cargo.toml"
+cp "$root/Cargo.lock" "$worktree/"
+(cd "$worktree" && cargo build-sbf)
+
+mkdir -p "$root/target/deploy"
+cp "$worktree/target/deploy/theforgeonsolana.so" "$root/target/deploy/theforgeonsolana_pack.so"
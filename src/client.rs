@@ -0,0 +1,311 @@
+//! Off-chain helpers for bots and backends: PDA addresses, instruction
+//! builders bound to a deployed forge, and account fetching over any RPC.
+
+use std::{cell::RefCell, collections::HashMap};
+
+use solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+};
+use thiserror::Error;
+
+use crate::{
+    error::SmeltingError,
+    instruction::{self, ForgeMints},
+    state::{
+        find_forge_authority_address, find_ingot_mint_address, find_ore_vault_address,
+        find_pending_smelt_address, find_smelting_state_address, find_user_stats_address,
+        PendingSmelt, SmeltingState, UserStats,
+    },
+};
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("RPC request failed: {0}")]
+    Rpc(String),
+    #[error("Account {0} does not exist")]
+    AccountNotFound(Pubkey),
+    #[error("Account {address} is owned by {owner}, not the forge program")]
+    InvalidOwner { address: Pubkey, owner: Pubkey },
+    #[error("Account {address} could not be decoded: {error}")]
+    Decode {
+        address: Pubkey,
+        error: ProgramError,
+    },
+}
+
+/// The parts of an on-chain account the client needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountData {
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+}
+
+/// Minimal RPC surface the client needs, so callers can plug in their own
+/// transport (or [`MockRpc`] in tests).
+pub trait ForgeRpc {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>, ClientError>;
+}
+
+#[cfg(feature = "rpc")]
+impl ForgeRpc for solana_rpc_client::rpc_client::RpcClient {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>, ClientError> {
+        let response = self
+            .get_account_with_commitment(address, self.commitment())
+            .map_err(|error| ClientError::Rpc(error.to_string()))?;
+        Ok(response.value.map(|account| AccountData {
+            owner: account.owner,
+            lamports: account.lamports,
+            data: account.data,
+        }))
+    }
+}
+
+/// In-memory accounts keyed by address.
+#[derive(Debug, Default)]
+pub struct MockRpc {
+    accounts: RefCell<HashMap<Pubkey, AccountData>>,
+}
+
+impl MockRpc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_account(&self, address: Pubkey, account: AccountData) {
+        self.accounts.borrow_mut().insert(address, account);
+    }
+
+    pub fn remove_account(&self, address: &Pubkey) {
+        self.accounts.borrow_mut().remove(address);
+    }
+
+    /// Stores a packed program-owned account, e.g. a `SmeltingState`.
+    pub fn set_packed<T: Pack>(&self, program_id: &Pubkey, address: Pubkey, value: T) {
+        let mut data = vec![0u8; T::LEN];
+        value.pack_into_slice(&mut data);
+        self.set_account(
+            address,
+            AccountData {
+                owner: *program_id,
+                lamports: 1,
+                data,
+            },
+        );
+    }
+}
+
+impl ForgeRpc for MockRpc {
+    fn get_account(&self, address: &Pubkey) -> Result<Option<AccountData>, ClientError> {
+        Ok(self.accounts.borrow().get(address).cloned())
+    }
+}
+
+/// Decodes a forge-owned account, checking the owner first.
+pub fn decode_account<T: Pack + IsInitialized>(
+    program_id: &Pubkey,
+    address: &Pubkey,
+    account: &AccountData,
+) -> Result<T, ClientError> {
+    if account.owner != *program_id {
+        return Err(ClientError::InvalidOwner {
+            address: *address,
+            owner: account.owner,
+        });
+    }
+    T::unpack(&account.data).map_err(|error| ClientError::Decode {
+        address: *address,
+        error,
+    })
+}
+
+/// Maps the custom code of a failed forge instruction back to its error.
+/// Token program failures inside a CPI use codes below the forge's range and
+/// decode to `None`.
+pub fn decode_instruction_error(error: &InstructionError) -> Option<SmeltingError> {
+    match error {
+        InstructionError::Custom(code) => SmeltingError::from_code(*code),
+        _ => None,
+    }
+}
+
+/// Addresses of a deployed forge, derived once from its program id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Forge {
+    pub program_id: Pubkey,
+    pub state: Pubkey,
+    pub forge_authority: Pubkey,
+    pub ingot_mint: Pubkey,
+    pub ore_vault: Pubkey,
+}
+
+impl Forge {
+    pub fn new(program_id: Pubkey) -> Self {
+        Self {
+            program_id,
+            state: find_smelting_state_address(&program_id).0,
+            forge_authority: find_forge_authority_address(&program_id).0,
+            ingot_mint: find_ingot_mint_address(&program_id).0,
+            ore_vault: find_ore_vault_address(&program_id).0,
+        }
+    }
+
+    pub fn pending_smelt_address(&self, user: &Pubkey) -> Pubkey {
+        find_pending_smelt_address(&self.program_id, user).0
+    }
+
+    pub fn user_stats_address(&self, user: &Pubkey) -> Pubkey {
+        find_user_stats_address(&self.program_id, user).0
+    }
+
+    pub fn fetch_state(&self, rpc: &impl ForgeRpc) -> Result<SmeltingState, ClientError> {
+        self.fetch(rpc, &self.state)?
+            .ok_or(ClientError::AccountNotFound(self.state))
+    }
+
+    pub fn fetch_pending_smelt(
+        &self,
+        rpc: &impl ForgeRpc,
+        user: &Pubkey,
+    ) -> Result<Option<PendingSmelt>, ClientError> {
+        self.fetch(rpc, &self.pending_smelt_address(user))
+    }
+
+    pub fn fetch_user_stats(
+        &self,
+        rpc: &impl ForgeRpc,
+        user: &Pubkey,
+    ) -> Result<Option<UserStats>, ClientError> {
+        self.fetch(rpc, &self.user_stats_address(user))
+    }
+
+    fn fetch<T: Pack + IsInitialized>(
+        &self,
+        rpc: &impl ForgeRpc,
+        address: &Pubkey,
+    ) -> Result<Option<T>, ClientError> {
+        rpc.get_account(address)?
+            .map(|account| decode_account(&self.program_id, address, &account))
+            .transpose()
+    }
+
+    /// Searches `attempts` nonces from `start` for one that meets the current
+    /// difficulty. Re-fetch the state after each smelt, as the challenge rotates.
+    pub fn find_nonce(
+        state: &SmeltingState,
+        user: &Pubkey,
+        start: u64,
+        attempts: u64,
+    ) -> Option<u64> {
+        (start..start.saturating_add(attempts)).find(|nonce| {
+            SmeltingState::meets_difficulty(&state.proof_hash(user, *nonce), state.difficulty)
+        })
+    }
+
+    /// Builds a `Smelt` with the mints and token programs taken from the fetched state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn smelt(
+        &self,
+        state: &SmeltingState,
+        user: &Pubkey,
+        ore_account: &Pubkey,
+        coal_account: &Pubkey,
+        ingot_account: &Pubkey,
+        amount: u64,
+        nonce: u64,
+        min_ingot_out: u64,
+    ) -> Instruction {
+        instruction::smelt(
+            &self.program_id,
+            user,
+            ore_account,
+            coal_account,
+            ingot_account,
+            &ForgeMints::from(state),
+            amount,
+            nonce,
+            min_ingot_out,
+        )
+    }
+
+    pub fn unsmelt(
+        &self,
+        state: &SmeltingState,
+        user: &Pubkey,
+        ore_account: &Pubkey,
+        ingot_account: &Pubkey,
+        amount: u64,
+        min_ore_out: u64,
+    ) -> Instruction {
+        instruction::unsmelt(
+            &self.program_id,
+            user,
+            ore_account,
+            ingot_account,
+            &ForgeMints::from(state),
+            amount,
+            min_ore_out,
+        )
+    }
+
+    pub fn commit_smelt(
+        &self,
+        state: &SmeltingState,
+        user: &Pubkey,
+        ore_account: &Pubkey,
+        coal_account: &Pubkey,
+        amount: u64,
+        secret: &[u8; 32],
+    ) -> Instruction {
+        instruction::commit_smelt(
+            &self.program_id,
+            user,
+            ore_account,
+            coal_account,
+            &ForgeMints::from(state),
+            amount,
+            PendingSmelt::commitment_for(secret, user),
+        )
+    }
+
+    pub fn reveal_smelt(
+        &self,
+        state: &SmeltingState,
+        user: &Pubkey,
+        ore_account: &Pubkey,
+        ingot_account: &Pubkey,
+        secret: [u8; 32],
+        min_ingot_out: u64,
+    ) -> Instruction {
+        instruction::reveal_smelt(
+            &self.program_id,
+            user,
+            ore_account,
+            ingot_account,
+            &ForgeMints::from(state),
+            secret,
+            min_ingot_out,
+        )
+    }
+
+    pub fn reclaim_smelt(
+        &self,
+        state: &SmeltingState,
+        user: &Pubkey,
+        ore_account: &Pubkey,
+    ) -> Instruction {
+        instruction::reclaim_smelt(
+            &self.program_id,
+            user,
+            ore_account,
+            &ForgeMints::from(state),
+        )
+    }
+
+    pub fn with_user_stats(&self, instruction: Instruction, user: &Pubkey) -> Instruction {
+        instruction::with_user_stats(instruction, &self.program_id, user)
+    }
+}
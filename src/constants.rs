@@ -1,9 +1,21 @@
-pub mod constants {
-    pub const SMELTING_SUCCESS_RATE: u8 = 80;
-    pub const WRAPPED_MINT_SEED: &[u8] = b"mint";
-    pub const BACKPOINTER_SEED: &[u8] = b"backpointer";
-    pub const AUTHORITY_SEED: &[u8] = b"authority";
-    pub const MAX_AMOUNT: u64 = 1_000_000_000; // 1 billion tokens
-    pub const MAX_INGOT_SUPPLY: u64 = 21_000_000;
-    pub const UNSMELT_FEE_PERCENTAGE: u8 = 5;
-}
+pub const SMELTING_SUCCESS_RATE: u8 = 80;
+pub const WRAPPED_MINT_SEED: &[u8] = b"mint";
+pub const BACKPOINTER_SEED: &[u8] = b"backpointer";
+pub const AUTHORITY_SEED: &[u8] = b"authority";
+pub const ORE_VAULT_SEED: &[u8] = b"vault";
+pub const PENDING_SMELT_SEED: &[u8] = b"pending";
+pub const ESCROW_SEED: &[u8] = b"escrow";
+pub const USER_STATS_SEED: &[u8] = b"stats";
+pub const MAX_AMOUNT: u64 = 1_000_000_000; // 1 billion tokens
+pub const MAX_INGOT_SUPPLY: u64 = 21_000_000;
+pub const UNSMELT_FEE_PERCENTAGE: u8 = 5;
+pub const MAX_UNSMELT_FEE_BPS: u16 = 1_000; // 10%
+pub const PAUSE_SMELT: u8 = 1 << 0;
+pub const PAUSE_UNSMELT: u8 = 1 << 1;
+pub const PAUSE_ALL: u8 = PAUSE_SMELT | PAUSE_UNSMELT;
+pub const MIN_DIFFICULTY: u8 = 8; // leading zero bits of the proof hash
+pub const MAX_DIFFICULTY: u8 = 64;
+pub const SMELT_COMMIT_TIMEOUT_SLOTS: u64 = 150; // must stay below the 512 entries kept in SlotHashes
+pub const MAX_METADATA_NAME_LEN: usize = 32; // Metaplex limits, also applied to Token-2022 metadata
+pub const MAX_METADATA_SYMBOL_LEN: usize = 10;
+pub const MAX_METADATA_URI_LEN: usize = 200;
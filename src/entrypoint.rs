@@ -1,12 +1,20 @@
 use solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, pubkey::Pubkey,
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult,
+    program_error::PrintProgramError, pubkey::Pubkey,
 };
 
+use crate::error::SmeltingError;
+
 entrypoint!(process_instruction);
 fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    crate::processor::Processor::process(program_id, accounts, instruction_data)
+    if let Err(error) = crate::processor::Processor::process(program_id, accounts, instruction_data)
+    {
+        error.print::<SmeltingError>();
+        return Err(error);
+    }
+    Ok(())
 }
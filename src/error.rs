@@ -1,14 +1,83 @@
-use solana_program::program_error::ProgramError;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive as _;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
 use thiserror::Error;
 
-#[derive(Error, Debug, Copy, Clone)]
+// Codes are part of the program's interface: variants are only ever appended
+// and a code is never reused. They start at 6000, like Anchor's, so they never
+// overlap the codes token programs return through a CPI.
+//
+// The first three variants were originally 0, 1 and 2 and were renumbered once,
+// before the codes were declared stable: MaxSupplyExceeded 0 -> 6000,
+// InsufficientBalance 1 -> 6001, InvalidInstruction 2 -> 6002.
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq, FromPrimitive)]
 pub enum SmeltingError {
     #[error("Max supply of INGOT tokens exceeded")]
-    MaxSupplyExceeded,
+    MaxSupplyExceeded = 6000,
     #[error("Insufficient balance")]
-    InsufficientBalance,
+    InsufficientBalance = 6001,
     #[error("Invalid instruction")]
-    InvalidInstruction,
+    InvalidInstruction = 6002,
+    #[error("Proof of work does not meet the required difficulty")]
+    InsufficientDifficulty = 6003,
+    #[error("Difficulty out of range")]
+    InvalidDifficulty = 6004,
+    #[error("A smelt is already pending for this user")]
+    PendingSmeltExists = 6005,
+    #[error("Revealed secret does not match the commitment")]
+    InvalidReveal = 6006,
+    #[error("Reveal must land in a later slot than the commit")]
+    RevealTooEarly = 6007,
+    #[error("Pending smelt has expired")]
+    CommitExpired = 6008,
+    #[error("Pending smelt has not expired yet")]
+    CommitNotExpired = 6009,
+    #[error("Slot hash for the commit slot is no longer available")]
+    SlotHashNotFound = 6010,
+    #[error("Wrap ratio numerator and denominator must be non-zero")]
+    InvalidWrapRatio = 6011,
+    #[error("Amount too small to convert at the current wrap ratio")]
+    AmountTooSmall = 6012,
+    #[error("Smelting state account is not owned by the program or is malformed")]
+    InvalidStateAccount = 6013,
+    #[error("Account is not the SPL Token or Token-2022 program expected for the mint")]
+    InvalidTokenProgram = 6014,
+    #[error("Token mint does not match the forge configuration")]
+    MintMismatch = 6015,
+    #[error("ORE vault does not match the forge configuration")]
+    InvalidVault = 6016,
+    #[error("Signer is not the forge authority")]
+    InvalidAuthority = 6017,
+    #[error("Token account is not held by the expected owner")]
+    InvalidTokenAccountOwner = 6018,
+    #[error("Account does not match the expected program address")]
+    InvalidPda = 6019,
+    #[error("Invalid sysvar account")]
+    InvalidSysvar = 6020,
+    #[error("Config value out of bounds")]
+    InvalidConfig = 6021,
+    #[error("Wrap ratio can only change while no INGOT is outstanding")]
+    WrapRatioLocked = 6022,
+    #[error("Operation is paused")]
+    Paused = 6023,
+    #[error("Not enough accrued fees")]
+    InsufficientFees = 6024,
+    #[error("Mint decimals do not match the forge configuration")]
+    DecimalsMismatch = 6025,
+    #[error("Smelting state uses an older layout, run MigrateState first")]
+    StateNotMigrated = 6026,
+    #[error("Amount is zero or above the maximum per transaction")]
+    InvalidAmount = 6027,
+    #[error("INGOT metadata name, symbol or URI is too long")]
+    MetadataTooLong = 6028,
+    #[error("Arithmetic overflow or underflow in forge accounting")]
+    MathOverflow = 6029,
+    #[error("Output is below the requested minimum")]
+    Slippage = 6030,
 }
 
 impl From<SmeltingError> for ProgramError {
@@ -16,3 +85,33 @@ impl From<SmeltingError> for ProgramError {
         ProgramError::Custom(e as u32)
     }
 }
+
+impl SmeltingError {
+    /// The error behind a `ProgramError::Custom` code raised by the forge.
+    ///
+    /// Only 6000 and up are forge codes. Programs built before the renumbering
+    /// returned 0, 1 and 2 for what are now 6000, 6001 and 6002; those low codes
+    /// decode to `None`, since today they come from a token program CPI.
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::from_u32(code)
+    }
+}
+
+impl<T> DecodeError<T> for SmeltingError {
+    fn type_of() -> &'static str {
+        "SmeltingError"
+    }
+}
+
+impl PrintProgramError for SmeltingError {
+    fn print<E>(&self)
+    where
+        E: 'static
+            + std::error::Error
+            + DecodeError<E>
+            + PrintProgramError
+            + num_traits::FromPrimitive,
+    {
+        msg!("Error: {}", self);
+    }
+}
@@ -0,0 +1,129 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{log::sol_log_data, pubkey::Pubkey};
+
+use crate::instruction::ConfigParam;
+
+// First `sol_log_data` field of every event, so foreign "Program data:" lines are skipped
+pub const EVENT_TAG: &[u8] = b"forge:event";
+
+const PROGRAM_PREFIX: &str = "Program ";
+const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct SmeltSucceeded {
+    pub user: Pubkey,
+    pub ore_amount: u64,
+    pub ingot_amount: u64,
+    pub coal_burned: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct SmeltFailed {
+    pub user: Pubkey,
+    pub ore_refunded: u64,
+    pub coal_burned: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct Unsmelted {
+    pub user: Pubkey,
+    pub ingot_amount: u64,
+    // What reached the user, after any ORE transfer fee
+    pub ore_returned: u64,
+    pub fee: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub enum ConfigChange {
+    Param(ConfigParam),
+    PausedOperations(u8),
+    AuthorityProposed(Pubkey),
+    AuthorityAccepted {
+        previous_authority: Pubkey,
+    },
+    IngotMetadata {
+        name: String,
+        symbol: String,
+        uri: String,
+    },
+    StateMigrated {
+        from_version: u8,
+    },
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub struct ConfigChanged {
+    pub authority: Pubkey,
+    pub change: ConfigChange,
+}
+
+// Variants are only ever appended, the Borsh tag is what indexers match on
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub enum SmeltingEvent {
+    SmeltSucceeded(SmeltSucceeded),
+    SmeltFailed(SmeltFailed),
+    Unsmelted(Unsmelted),
+    ConfigChanged(ConfigChanged),
+}
+
+impl SmeltingEvent {
+    pub fn emit(&self) {
+        let data = borsh::to_vec(self).expect("writing to a Vec cannot fail");
+        sol_log_data(&[EVENT_TAG, &data]);
+    }
+
+    /// Decodes the fields of a single `sol_log_data` call.
+    pub fn decode(fields: &[&[u8]]) -> Option<Self> {
+        match fields {
+            [tag, data] if *tag == EVENT_TAG => Self::try_from_slice(data).ok(),
+            _ => None,
+        }
+    }
+
+    // A `Program data: <base64> <base64>` line, whoever logged it
+    fn decode_log(log: &str) -> Option<Self> {
+        let fields = log
+            .strip_prefix(PROGRAM_DATA_PREFIX)?
+            .split(' ')
+            .map(|field| STANDARD.decode(field).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let fields = fields.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        Self::decode(&fields)
+    }
+
+    /// Decodes every event the forge at `program_id` logged in a transaction's log
+    /// messages, in emission order. Data lines only count while the forge's own
+    /// invocation is the innermost one, so other programs can't log forged events.
+    pub fn decode_logs<'a>(
+        program_id: &Pubkey,
+        logs: impl IntoIterator<Item = &'a str>,
+    ) -> Vec<Self> {
+        let program_id = program_id.to_string();
+        let mut frames = Vec::new();
+        let mut events = Vec::new();
+        for log in logs {
+            let Some(rest) = log.strip_prefix(PROGRAM_PREFIX) else {
+                continue;
+            };
+            let mut words = rest.split(' ');
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => frames.push(program),
+                (Some(program), Some("success" | "failed:")) if frames.last() == Some(&program) => {
+                    frames.pop();
+                }
+                (Some("data:"), _) if frames.last() == Some(&program_id.as_str()) => {
+                    events.extend(Self::decode_log(log));
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,662 @@
+//! Anchor-style IDL for the forge. Instruction arguments, events and type
+//! layouts come from the Borsh schemas of the Rust definitions, error codes
+//! from `SmeltingError`. Account names, PDAs and addresses are written out by
+//! hand, their signer and writable flags are taken from the instruction builders.
+//! `idl/theforgeonsolana.json` is the checked-in output of `cargo run
+//! --example idl --features idl` and `tests/idl.rs` fails when it drifts.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anchor_lang_idl_spec::{
+    Idl, IdlAccount, IdlDefinedFields, IdlEnumVariant, IdlErrorCode, IdlEvent, IdlField,
+    IdlInstruction, IdlInstructionAccount, IdlInstructionAccountItem, IdlMetadata, IdlPda, IdlRepr,
+    IdlReprModifier, IdlSeed, IdlSeedAccount, IdlSeedConst, IdlSerialization, IdlType, IdlTypeDef,
+    IdlTypeDefTy, IDL_SPEC,
+};
+use borsh::{
+    schema::{Declaration, Definition, Fields},
+    BorshSchema,
+};
+use num_traits::FromPrimitive;
+use solana_program::{
+    bpf_loader_upgradeable, instruction::Instruction, pubkey::Pubkey, system_program,
+    sysvar::slot_hashes,
+};
+
+use crate::{
+    constants::{
+        AUTHORITY_SEED, BACKPOINTER_SEED, ESCROW_SEED, ORE_VAULT_SEED, PENDING_SMELT_SEED,
+        USER_STATS_SEED, WRAPPED_MINT_SEED,
+    },
+    error::SmeltingError,
+    events::{SmeltingEvent, EVENT_TAG},
+    instruction::{self, ConfigParam, ForgeMints, SmeltingInstruction, INSTRUCTION_VERSION},
+    metaplex,
+    state::{SmeltingState, STATE_HEADER_LEN, STATE_VERSION},
+};
+
+type Definitions = BTreeMap<Declaration, Definition>;
+
+pub fn idl() -> Idl {
+    let mut definitions = Definitions::new();
+    SmeltingInstruction::add_definitions_recursively(&mut definitions);
+    SmeltingEvent::add_definitions_recursively(&mut definitions);
+    SmeltingState::add_definitions_recursively(&mut definitions);
+
+    let built = every_instruction(&crate::ID);
+    let instructions = variants::<SmeltingInstruction>(&definitions)
+        .map(|(tag, name, fields)| {
+            let discriminator = vec![INSTRUCTION_VERSION, tag];
+            let (docs, mut accounts) = instruction_accounts(name);
+            let instruction = built
+                .iter()
+                .find(|instruction| instruction.data.starts_with(&discriminator))
+                .unwrap_or_else(|| panic!("{name} has no builder in `every_instruction`"));
+            flags_from_builder(name, &mut accounts, instruction);
+            IdlInstruction {
+                name: snake_case(name),
+                docs: vec![docs.to_owned()],
+                discriminator,
+                accounts: accounts.into_iter().map(Account::into_item).collect(),
+                args: match defined_fields(fields) {
+                    Some(IdlDefinedFields::Named(args)) => args,
+                    None => vec![],
+                    Some(IdlDefinedFields::Tuple(_)) => panic!("{name} has unnamed arguments"),
+                },
+                returns: None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Every event variant wraps a struct of the same name
+    let events = variants::<SmeltingEvent>(&definitions)
+        .map(|(tag, name, _)| IdlEvent {
+            name: name.to_owned(),
+            discriminator: vec![tag],
+        })
+        .collect::<Vec<_>>();
+
+    let mut header = vec![0; STATE_HEADER_LEN];
+    header[0] = STATE_VERSION;
+    let accounts = vec![IdlAccount {
+        name: SmeltingState::declaration(),
+        discriminator: header,
+    }];
+
+    let mut roots = instructions
+        .iter()
+        .flat_map(|instruction| &instruction.args)
+        .map(|arg| arg.ty.clone())
+        .collect::<Vec<_>>();
+    roots.extend(events.iter().map(|event| defined(&event.name)));
+    roots.push(defined(&SmeltingState::declaration()));
+    let types = type_defs(&definitions, &roots)
+        .into_iter()
+        .map(|mut type_def| {
+            if type_def.name == SmeltingState::declaration() {
+                type_def.serialization = IdlSerialization::Bytemuck;
+                type_def.repr = Some(IdlRepr::C(IdlReprModifier {
+                    packed: false,
+                    align: None,
+                }));
+            }
+            type_def
+        })
+        .collect();
+
+    let errors = (SmeltingError::MaxSupplyExceeded as u32..)
+        .map_while(SmeltingError::from_u32)
+        .map(|error| IdlErrorCode {
+            code: error as u32,
+            name: format!("{error:?}"),
+            msg: Some(error.to_string()),
+        })
+        .collect();
+
+    Idl {
+        address: crate::ID.to_string(),
+        metadata: IdlMetadata {
+            name: env!("CARGO_PKG_NAME").to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            spec: IDL_SPEC.to_owned(),
+            description: Some(env!("CARGO_PKG_DESCRIPTION").to_owned()),
+            repository: Some(env!("CARGO_PKG_REPOSITORY").to_owned()),
+            dependencies: vec![],
+            contact: None,
+            deployments: None,
+        },
+        docs: vec![
+            "Instruction data is the discriminator (the instruction version byte, then the Borsh variant tag) followed by the Borsh arguments.".to_owned(),
+            format!(
+                "Events are logged as `sol_log_data([{:?}, discriminator ++ Borsh fields])`.",
+                std::str::from_utf8(EVENT_TAG).expect("event tag is ASCII"),
+            ),
+            "Account discriminators are the header in front of the account data, the first byte is the layout version.".to_owned(),
+        ],
+        instructions,
+        accounts,
+        events,
+        errors,
+        types,
+        constants: vec![],
+    }
+}
+
+/// The IDL as pretty-printed JSON, exactly as checked in.
+pub fn idl_json() -> String {
+    let mut json = serde_json::to_string_pretty(&idl()).expect("the IDL serializes");
+    json.push('\n');
+    json
+}
+
+// (Borsh tag, variant name, variant fields) of an enum
+fn variants<T: BorshSchema>(
+    definitions: &Definitions,
+) -> impl Iterator<Item = (u8, &str, &Fields)> {
+    let Some(Definition::Enum { variants, .. }) = definitions.get(&T::declaration()) else {
+        panic!("{} is not an enum", T::declaration());
+    };
+    variants.iter().map(|(tag, name, declaration)| {
+        let tag = u8::try_from(*tag).expect("one byte Borsh tag");
+        match &definitions[declaration] {
+            Definition::Struct { fields } => (tag, name.as_str(), fields),
+            _ => panic!("{declaration} is not a variant"),
+        }
+    })
+}
+
+fn idl_type(declaration: &str) -> IdlType {
+    declaration
+        .parse()
+        .unwrap_or_else(|_| panic!("{declaration} has no IDL type"))
+}
+
+fn defined(name: &str) -> IdlType {
+    IdlType::Defined {
+        name: name.to_owned(),
+        generics: vec![],
+    }
+}
+
+fn defined_fields(fields: &Fields) -> Option<IdlDefinedFields> {
+    match fields {
+        Fields::NamedFields(fields) => Some(IdlDefinedFields::Named(
+            fields
+                .iter()
+                .map(|(name, declaration)| IdlField {
+                    name: name.clone(),
+                    docs: vec![],
+                    ty: idl_type(declaration),
+                })
+                .collect(),
+        )),
+        Fields::UnnamedFields(fields) => Some(IdlDefinedFields::Tuple(
+            fields
+                .iter()
+                .map(|declaration| idl_type(declaration))
+                .collect(),
+        )),
+        Fields::Empty => None,
+    }
+}
+
+// Type definitions for every defined type reachable from `roots`, sorted by name
+fn type_defs(definitions: &Definitions, roots: &[IdlType]) -> Vec<IdlTypeDef> {
+    let mut pending = roots.to_vec();
+    let mut seen = BTreeSet::new();
+    let mut type_defs = vec![];
+    while let Some(ty) = pending.pop() {
+        let name = match ty {
+            IdlType::Defined { name, .. } => name,
+            IdlType::Option(inner) | IdlType::Vec(inner) | IdlType::Array(inner, _) => {
+                pending.push(*inner);
+                continue;
+            }
+            _ => continue,
+        };
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        let ty = match &definitions[&name] {
+            Definition::Struct { fields } => IdlTypeDefTy::Struct {
+                fields: defined_fields(fields),
+            },
+            Definition::Enum { variants, .. } => IdlTypeDefTy::Enum {
+                variants: variants
+                    .iter()
+                    .map(|(_, variant, declaration)| IdlEnumVariant {
+                        name: variant.clone(),
+                        fields: match &definitions[declaration] {
+                            Definition::Struct { fields } => defined_fields(fields),
+                            _ => panic!("{declaration} is not a variant"),
+                        },
+                    })
+                    .collect(),
+            },
+            _ => panic!("{name} is neither a struct nor an enum"),
+        };
+        pending.extend(field_types(&ty));
+        type_defs.push(IdlTypeDef {
+            name,
+            docs: vec![],
+            serialization: IdlSerialization::Borsh,
+            repr: None,
+            generics: vec![],
+            ty,
+        });
+    }
+    type_defs.sort_by(|a, b| a.name.cmp(&b.name));
+    type_defs
+}
+
+fn field_types(ty: &IdlTypeDefTy) -> Vec<IdlType> {
+    let fields = match ty {
+        IdlTypeDefTy::Struct { fields } => vec![fields],
+        IdlTypeDefTy::Enum { variants } => variants.iter().map(|variant| &variant.fields).collect(),
+        IdlTypeDefTy::Type { alias } => return vec![alias.clone()],
+    };
+    fields
+        .into_iter()
+        .flatten()
+        .flat_map(|fields| match fields {
+            IdlDefinedFields::Named(fields) => {
+                fields.iter().map(|field| field.ty.clone()).collect()
+            }
+            IdlDefinedFields::Tuple(types) => types.clone(),
+        })
+        .collect()
+}
+
+/// One instruction from every builder in `instruction`, with placeholder accounts.
+pub fn every_instruction(program_id: &Pubkey) -> Vec<Instruction> {
+    let key = |n: u8| Pubkey::new_from_array([n; 32]);
+    let (user, ore, coal, ingot) = (key(1), key(2), key(3), key(4));
+    let mints = ForgeMints {
+        ore_mint: key(5),
+        coal_mint: key(6),
+        ore_token_program: spl_token_2022::id(),
+        coal_token_program: spl_token::id(),
+        ingot_token_program: spl_token::id(),
+    };
+    vec![
+        instruction::smelt(program_id, &user, &ore, &coal, &ingot, &mints, 1, 2, 3),
+        instruction::unsmelt(program_id, &user, &ore, &ingot, &mints, 1, 2),
+        instruction::transfer_ore(program_id, &ore, &coal, &user, &mints, 1),
+        instruction::transfer_ingot(program_id, &ingot, &coal, &user, &mints, 1),
+        instruction::initialize(
+            program_id,
+            &user,
+            &mints.ore_mint,
+            &mints.coal_mint,
+            &mints.ore_token_program,
+            &mints.ingot_token_program,
+            6,
+            1,
+            1,
+        ),
+        instruction::commit_smelt(program_id, &user, &ore, &coal, &mints, 1, [0; 32]),
+        instruction::reveal_smelt(program_id, &user, &ore, &ingot, &mints, [0; 32], 1),
+        instruction::reclaim_smelt(program_id, &user, &ore, &mints),
+        instruction::update_config(program_id, &user, ConfigParam::MaxAmount(1)),
+        instruction::propose_authority(program_id, &user, &key(7)),
+        instruction::accept_authority(program_id, &user),
+        instruction::set_pause(program_id, &user, 0),
+        instruction::withdraw_fees(program_id, &user, &ore, &mints, 1),
+        instruction::close_user_stats(program_id, &user),
+        instruction::set_ingot_metadata(
+            program_id,
+            &user,
+            &mints,
+            "Ingot".to_owned(),
+            "INGOT".to_owned(),
+            "https://example.com/ingot.json".to_owned(),
+        ),
+        instruction::migrate_state(program_id, &user),
+    ]
+}
+
+// Optional accounts the builder leaves out are the ones `with_user_stats` appends, which
+// also makes the user writable, so the listed accounts keep the plain builder's flags
+fn flags_from_builder(name: &str, accounts: &mut [Account], instruction: &Instruction) {
+    let with_stats = (instruction.accounts.len() < accounts.len()).then(|| {
+        let user = instruction.accounts[0].pubkey;
+        instruction::with_user_stats(instruction.clone(), &crate::ID, &user)
+    });
+    let metas = with_stats.as_ref().unwrap_or(instruction).accounts.iter();
+    let metas = instruction
+        .accounts
+        .iter()
+        .chain(metas.skip(instruction.accounts.len()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        metas.len(),
+        accounts.len(),
+        "{name} lists {} accounts, its builder takes {}",
+        accounts.len(),
+        metas.len()
+    );
+    for (account, meta) in accounts.iter_mut().zip(metas) {
+        account.0.signer = meta.is_signer;
+        account.0.writable = meta.is_writable;
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+struct Account(IdlInstructionAccount);
+
+impl Account {
+    fn new(name: &str) -> Self {
+        Self(IdlInstructionAccount {
+            name: name.to_owned(),
+            docs: vec![],
+            writable: false,
+            signer: false,
+            optional: false,
+            address: None,
+            pda: None,
+            relations: vec![],
+        })
+    }
+
+    fn optional(mut self) -> Self {
+        self.0.optional = true;
+        self
+    }
+
+    fn address(mut self, address: Pubkey) -> Self {
+        self.0.address = Some(address.to_string());
+        self
+    }
+
+    fn pda(mut self, seeds: Vec<IdlSeed>) -> Self {
+        self.0.pda = Some(IdlPda {
+            seeds,
+            program: None,
+        });
+        self
+    }
+
+    // For PDAs of another program
+    fn pda_program(mut self, program: Pubkey) -> Self {
+        if let Some(pda) = &mut self.0.pda {
+            pda.program = Some(const_seed(program.as_ref()));
+        }
+        self
+    }
+
+    fn into_item(self) -> IdlInstructionAccountItem {
+        IdlInstructionAccountItem::Single(self.0)
+    }
+}
+
+fn const_seed(value: &[u8]) -> IdlSeed {
+    IdlSeed::Const(IdlSeedConst {
+        value: value.to_vec(),
+    })
+}
+
+fn account_seed(path: &str) -> IdlSeed {
+    IdlSeed::Account(IdlSeedAccount {
+        path: path.to_owned(),
+        account: None,
+    })
+}
+
+fn smelting_state() -> Account {
+    Account::new("smelting_state").pda(vec![const_seed(BACKPOINTER_SEED)])
+}
+
+fn forge_authority() -> Account {
+    Account::new("forge_authority").pda(vec![const_seed(AUTHORITY_SEED)])
+}
+
+fn ingot_mint() -> Account {
+    Account::new("ingot_mint").pda(vec![const_seed(WRAPPED_MINT_SEED)])
+}
+
+fn ore_vault() -> Account {
+    Account::new("ore_vault").pda(vec![const_seed(ORE_VAULT_SEED)])
+}
+
+fn pending_smelt() -> Account {
+    Account::new("pending_smelt").pda(vec![const_seed(PENDING_SMELT_SEED), account_seed("user")])
+}
+
+fn escrow(name: &str, mint: &str) -> Account {
+    Account::new(name).pda(vec![
+        const_seed(ESCROW_SEED),
+        account_seed("pending_smelt"),
+        account_seed(mint),
+    ])
+}
+
+fn system_program() -> Account {
+    Account::new("system_program").address(system_program::id())
+}
+
+// The accounts appended by `with_user_stats`
+fn user_stats() -> [Account; 2] {
+    [
+        Account::new("user_stats")
+            .optional()
+            .pda(vec![const_seed(USER_STATS_SEED), account_seed("user")]),
+        system_program().optional(),
+    ]
+}
+
+fn admin_accounts(authority: &str) -> Vec<Account> {
+    vec![Account::new(authority), smelting_state()]
+}
+
+// Names, PDAs and addresses for the "Accounts:" lists of the builders in `instruction`,
+// signer and writable flags come from the builders themselves
+fn instruction_accounts(name: &str) -> (&'static str, Vec<Account>) {
+    match name {
+        "Smelt" => {
+            let mut accounts = vec![
+                Account::new("user"),
+                Account::new("ore_account"),
+                Account::new("coal_account"),
+                Account::new("ingot_account"),
+                smelting_state(),
+                forge_authority(),
+                ore_vault(),
+                Account::new("ore_mint"),
+                Account::new("coal_mint"),
+                ingot_mint(),
+                Account::new("ore_token_program"),
+                Account::new("coal_token_program"),
+                Account::new("ingot_token_program"),
+            ];
+            accounts.extend(user_stats());
+            ("Burns COAL to smelt ORE into INGOT behind a proof of work.", accounts)
+        }
+        "Unsmelt" => {
+            let mut accounts = vec![
+                Account::new("user"),
+                Account::new("ore_account"),
+                Account::new("ingot_account"),
+                smelting_state(),
+                forge_authority(),
+                ore_vault(),
+                Account::new("ore_mint"),
+                ingot_mint(),
+                Account::new("ore_token_program"),
+                Account::new("ingot_token_program"),
+            ];
+            accounts.extend(user_stats());
+            ("Burns INGOT and returns the ORE behind it, less the unsmelt fee.", accounts)
+        }
+        "TransferOre" => (
+            "Transfers ORE between token accounts.",
+            vec![
+                Account::new("source"),
+                Account::new("destination"),
+                Account::new("owner"),
+                Account::new("ore_token_program"),
+                smelting_state(),
+                Account::new("ore_mint"),
+            ],
+        ),
+        "TransferIngot" => (
+            "Transfers INGOT between token accounts.",
+            vec![
+                Account::new("source"),
+                Account::new("destination"),
+                Account::new("owner"),
+                Account::new("ingot_token_program"),
+                smelting_state(),
+                ingot_mint(),
+            ],
+        ),
+        "Initialize" => (
+            "Creates the smelting state, the INGOT mint and the ORE vault. Only the program's upgrade authority can initialize.",
+            vec![
+                Account::new("authority"),
+                smelting_state(),
+                forge_authority(),
+                Account::new("ore_mint"),
+                Account::new("coal_mint"),
+                ingot_mint(),
+                ore_vault(),
+                Account::new("ingot_token_program"),
+                system_program(),
+                Account::new("ore_token_program"),
+                Account::new("program_data")
+                    .pda(vec![const_seed(crate::ID.as_ref())])
+                    .pda_program(bpf_loader_upgradeable::id()),
+            ],
+        ),
+        "CommitSmelt" => (
+            "Escrows ORE and COAL behind a commitment to a secret.",
+            vec![
+                Account::new("user"),
+                Account::new("ore_account"),
+                Account::new("coal_account"),
+                smelting_state(),
+                pending_smelt(),
+                escrow("ore_escrow", "ore_mint"),
+                escrow("coal_escrow", "coal_mint"),
+                Account::new("ore_mint"),
+                Account::new("coal_mint"),
+                Account::new("ore_token_program"),
+                system_program(),
+                Account::new("coal_token_program"),
+            ],
+        ),
+        "RevealSmelt" => {
+            let mut accounts = vec![
+                Account::new("user"),
+                Account::new("ore_account"),
+                Account::new("ingot_account"),
+                smelting_state(),
+                pending_smelt(),
+                escrow("ore_escrow", "ore_mint"),
+                escrow("coal_escrow", "coal_mint"),
+                forge_authority(),
+                ore_vault(),
+                Account::new("ore_mint"),
+                Account::new("coal_mint"),
+                ingot_mint(),
+                Account::new("ore_token_program"),
+                Account::new("coal_token_program"),
+                Account::new("ingot_token_program"),
+                Account::new("slot_hashes").address(slot_hashes::id()),
+            ];
+            accounts.extend(user_stats());
+            ("Reveals the secret of a pending smelt and settles it.", accounts)
+        }
+        "ReclaimSmelt" => (
+            "Returns the escrowed ORE of an expired pending smelt, the COAL is burned.",
+            vec![
+                Account::new("user"),
+                Account::new("ore_account"),
+                smelting_state(),
+                pending_smelt(),
+                escrow("ore_escrow", "ore_mint"),
+                escrow("coal_escrow", "coal_mint"),
+                Account::new("coal_mint"),
+                forge_authority(),
+                Account::new("ore_token_program"),
+                Account::new("ore_mint"),
+                Account::new("coal_token_program"),
+            ],
+        ),
+        "UpdateConfig" => ("Updates one config value.", admin_accounts("authority")),
+        "ProposeAuthority" => (
+            "Proposes a new authority, which takes over once it accepts.",
+            admin_accounts("authority"),
+        ),
+        "AcceptAuthority" => (
+            "Accepts a proposed authority transfer.",
+            admin_accounts("pending_authority"),
+        ),
+        "SetPause" => (
+            "Sets the mask of paused operations.",
+            admin_accounts("authority"),
+        ),
+        "WithdrawFees" => (
+            "Withdraws accrued unsmelt fees from the ORE vault.",
+            vec![
+                Account::new("withdraw_authority"),
+                smelting_state(),
+                ore_vault(),
+                Account::new("destination"),
+                forge_authority(),
+                Account::new("ore_token_program"),
+                Account::new("ore_mint"),
+            ],
+        ),
+        "CloseUserStats" => (
+            "Closes the user's stats account and returns its rent.",
+            vec![
+                Account::new("user"),
+                Account::new("user_stats")
+                    .pda(vec![const_seed(USER_STATS_SEED), account_seed("user")]),
+            ],
+        ),
+        "SetIngotMetadata" => {
+            (
+                "Sets the INGOT name, symbol and URI, in the mint under Token-2022 or in a Metaplex metadata account under SPL Token.",
+                vec![
+                    Account::new("authority"),
+                    smelting_state(),
+                    ingot_mint(),
+                    forge_authority(),
+                    Account::new("ingot_token_program"),
+                    system_program(),
+                    Account::new("metadata")
+                        .optional()
+                        .pda(vec![
+                            const_seed(metaplex::METADATA_SEED),
+                            const_seed(metaplex::ID.as_ref()),
+                            account_seed("ingot_mint"),
+                        ])
+                        .pda_program(metaplex::ID),
+                    Account::new("token_metadata_program")
+                        .optional()
+                        .address(metaplex::ID),
+                ],
+            )
+        }
+        "MigrateState" => (
+            "Rewrites the smelting state in the current layout.",
+            vec![
+                Account::new("authority"),
+                smelting_state(),
+                system_program(),
+            ],
+        ),
+        _ => panic!("no accounts listed for {name}"),
+    }
+}
@@ -1,32 +1,780 @@
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
+    bpf_loader_upgradeable,
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_program,
+    sysvar::slot_hashes,
 };
 
-#[derive(Debug)]
+use crate::{
+    error::SmeltingError,
+    metaplex,
+    state::{
+        find_escrow_address, find_forge_authority_address, find_ingot_mint_address,
+        find_ore_vault_address, find_pending_smelt_address, find_smelting_state_address,
+        find_user_stats_address, SmeltingState,
+    },
+};
+
+// Leading byte of every instruction, bumped whenever the Borsh layout changes
+pub const INSTRUCTION_VERSION: u8 = 3;
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
 pub enum SmeltingInstruction {
-    Smelt { amount: u64 },
-    Unsmelt { amount: u64 },
-    MintIngot { amount: u64 },
-    TransferOre { amount: u64 },
-    TransferIngot { amount: u64 },
+    Smelt {
+        amount: u64,
+        nonce: u64,
+        min_ingot_out: u64,
+    },
+    Unsmelt {
+        amount: u64,
+        min_ore_out: u64,
+    },
+    TransferOre {
+        amount: u64,
+    },
+    TransferIngot {
+        amount: u64,
+    },
+    Initialize {
+        ingot_decimals: u8,
+        wrap_ratio_numerator: u64,
+        wrap_ratio_denominator: u64,
+    },
+    CommitSmelt {
+        amount: u64,
+        commitment: [u8; 32],
+    },
+    RevealSmelt {
+        secret: [u8; 32],
+        min_ingot_out: u64,
+    },
+    ReclaimSmelt,
+    UpdateConfig {
+        param: ConfigParam,
+    },
+    ProposeAuthority {
+        new_authority: Pubkey,
+    },
+    AcceptAuthority,
+    SetPause {
+        paused_operations: u8,
+    },
+    WithdrawFees {
+        amount: u64,
+    },
+    CloseUserStats,
+    SetIngotMetadata {
+        name: String,
+        symbol: String,
+        uri: String,
+    },
+    MigrateState,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
+pub enum ConfigParam {
+    SuccessRate(u8),
+    UnsmeltFeeBps(u16),
+    MaxIngotSupply(u64),
+    MaxAmount(u64),
+    WrapRatio {
+        numerator: u64,
+        denominator: u64,
+    },
+    Treasury(Pubkey),
+    Difficulty(u8),
+    CoalCost {
+        coal_per_ore: u64,
+        coal_base_cost: u64,
+    },
 }
 
 impl SmeltingInstruction {
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        let (&tag, rest) = input
+        let (&version, rest) = input
             .split_first()
-            .ok_or(ProgramError::InvalidInstructionData)?;
-        let amount = u64::from_le_bytes(rest.try_into().map_err(|_| ProgramError::InvalidInstructionData)?);
-
-        Ok(match tag {
-            0 => Self::Smelt { amount },
-            1 => Self::Unsmelt { amount },
-            2 => Self::MintIngot { amount },
-            3 => Self::TransferOre { amount },
-            4 => Self::TransferIngot { amount },
-            _ => return Err(ProgramError::InvalidInstructionData),
-        })
+            .ok_or(SmeltingError::InvalidInstruction)?;
+        if version != INSTRUCTION_VERSION {
+            return Err(SmeltingError::InvalidInstruction.into());
+        }
+        Self::try_from_slice(rest).map_err(|_| SmeltingError::InvalidInstruction.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = vec![INSTRUCTION_VERSION];
+        borsh::to_writer(&mut buf, self).expect("writing to a Vec cannot fail");
+        buf
+    }
+}
+
+/// The external mints and the token program each forge mint lives under, as
+/// recorded in the smelting state. ORE, COAL and INGOT may each use either
+/// SPL Token or Token-2022.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForgeMints {
+    pub ore_mint: Pubkey,
+    pub coal_mint: Pubkey,
+    pub ore_token_program: Pubkey,
+    pub coal_token_program: Pubkey,
+    pub ingot_token_program: Pubkey,
+}
+
+impl From<&SmeltingState> for ForgeMints {
+    fn from(smelting_state: &SmeltingState) -> Self {
+        Self {
+            ore_mint: smelting_state.ore_mint,
+            coal_mint: smelting_state.coal_mint,
+            ore_token_program: smelting_state.ore_token_program,
+            coal_token_program: smelting_state.coal_token_program,
+            ingot_token_program: smelting_state.ingot_token_program,
+        }
+    }
+}
+
+fn instruction(
+    program_id: &Pubkey,
+    data: SmeltingInstruction,
+    accounts: Vec<AccountMeta>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.pack(),
     }
 }
+
+// Admin instructions all take the stored authority and the state account
+fn admin_instruction(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    data: SmeltingInstruction,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    instruction(
+        program_id,
+        data,
+        vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(state, false),
+        ],
+    )
+}
+
+/// Creates an `Initialize` instruction. The INGOT mint is created under
+/// `ingot_token_program`, the ORE and COAL token programs are taken from
+/// their mints.
+///
+/// Accounts:
+/// 0. `[signer, writable]` Authority, pays for the new accounts
+/// 1. `[writable]` Smelting state PDA
+/// 2. `[]` Forge authority PDA
+/// 3. `[]` ORE mint
+/// 4. `[]` COAL mint
+/// 5. `[writable]` INGOT mint PDA
+/// 6. `[writable]` ORE vault PDA
+/// 7. `[]` INGOT token program
+/// 8. `[]` System program
+/// 9. `[]` ORE token program
+/// 10. `[]` Program data account, whose upgrade authority must be the authority
+#[allow(clippy::too_many_arguments)]
+pub fn initialize(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    ore_mint: &Pubkey,
+    coal_mint: &Pubkey,
+    ore_token_program: &Pubkey,
+    ingot_token_program: &Pubkey,
+    ingot_decimals: u8,
+    wrap_ratio_numerator: u64,
+    wrap_ratio_denominator: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::Initialize {
+            ingot_decimals,
+            wrap_ratio_numerator,
+            wrap_ratio_denominator,
+        },
+        vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new_readonly(*ore_mint, false),
+            AccountMeta::new_readonly(*coal_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new_readonly(*ingot_token_program, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*ore_token_program, false),
+            AccountMeta::new_readonly(
+                bpf_loader_upgradeable::get_program_data_address(program_id),
+                false,
+            ),
+        ],
+    )
+}
+
+/// Creates a proof-of-work `Smelt` instruction.
+///
+/// Accounts:
+/// 0. `[signer]` User
+/// 1. `[writable]` User's ORE account
+/// 2. `[writable]` User's COAL account
+/// 3. `[writable]` User's INGOT account
+/// 4. `[writable]` Smelting state PDA
+/// 5. `[]` Forge authority PDA
+/// 6. `[writable]` ORE vault PDA
+/// 7. `[]` ORE mint
+/// 8. `[writable]` COAL mint
+/// 9. `[writable]` INGOT mint PDA
+/// 10. `[]` ORE token program
+/// 11. `[]` COAL token program
+/// 12. `[]` INGOT token program
+///
+/// Fails with `Slippage` when fewer than `min_ingot_out` INGOT would be minted.
+/// Per-user stats are recorded when the instruction goes through [`with_user_stats`].
+#[allow(clippy::too_many_arguments)]
+pub fn smelt(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    ore_account: &Pubkey,
+    coal_account: &Pubkey,
+    ingot_account: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+    nonce: u64,
+    min_ingot_out: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::Smelt {
+            amount,
+            nonce,
+            min_ingot_out,
+        },
+        vec![
+            AccountMeta::new_readonly(*user, true),
+            AccountMeta::new(*ore_account, false),
+            AccountMeta::new(*coal_account, false),
+            AccountMeta::new(*ingot_account, false),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new_readonly(mints.ore_mint, false),
+            AccountMeta::new(mints.coal_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new_readonly(mints.coal_token_program, false),
+            AccountMeta::new_readonly(mints.ingot_token_program, false),
+        ],
+    )
+}
+
+/// Creates an `Unsmelt` instruction.
+///
+/// Accounts:
+/// 0. `[signer]` User
+/// 1. `[writable]` User's ORE account
+/// 2. `[writable]` User's INGOT account
+/// 3. `[writable]` Smelting state PDA
+/// 4. `[]` Forge authority PDA
+/// 5. `[writable]` ORE vault PDA
+/// 6. `[]` ORE mint
+/// 7. `[writable]` INGOT mint PDA
+/// 8. `[]` ORE token program
+/// 9. `[]` INGOT token program
+///
+/// Fails with `Slippage` when less than `min_ore_out` ORE reaches the user.
+/// Per-user stats are recorded when the instruction goes through [`with_user_stats`].
+pub fn unsmelt(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    ore_account: &Pubkey,
+    ingot_account: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+    min_ore_out: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::Unsmelt {
+            amount,
+            min_ore_out,
+        },
+        vec![
+            AccountMeta::new_readonly(*user, true),
+            AccountMeta::new(*ore_account, false),
+            AccountMeta::new(*ingot_account, false),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new_readonly(mints.ore_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new_readonly(mints.ingot_token_program, false),
+        ],
+    )
+}
+
+fn transfer_accounts(
+    program_id: &Pubkey,
+    source: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+    token_program: &Pubkey,
+    mint: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (state, _) = find_smelting_state_address(program_id);
+    vec![
+        AccountMeta::new(*source, false),
+        AccountMeta::new(*destination, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*token_program, false),
+        AccountMeta::new_readonly(state, false),
+        AccountMeta::new_readonly(*mint, false),
+    ]
+}
+
+/// Creates a `TransferOre` instruction.
+///
+/// Accounts:
+/// 0. `[writable]` Source ORE account
+/// 1. `[writable]` Destination ORE account
+/// 2. `[signer]` Source owner
+/// 3. `[]` ORE token program
+/// 4. `[]` Smelting state PDA
+/// 5. `[]` ORE mint
+pub fn transfer_ore(
+    program_id: &Pubkey,
+    source: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+) -> Instruction {
+    instruction(
+        program_id,
+        SmeltingInstruction::TransferOre { amount },
+        transfer_accounts(
+            program_id,
+            source,
+            destination,
+            owner,
+            &mints.ore_token_program,
+            &mints.ore_mint,
+        ),
+    )
+}
+
+/// Creates a `TransferIngot` instruction.
+///
+/// Accounts:
+/// 0. `[writable]` Source INGOT account
+/// 1. `[writable]` Destination INGOT account
+/// 2. `[signer]` Source owner
+/// 3. `[]` INGOT token program
+/// 4. `[]` Smelting state PDA
+/// 5. `[]` INGOT mint PDA
+pub fn transfer_ingot(
+    program_id: &Pubkey,
+    source: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+) -> Instruction {
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::TransferIngot { amount },
+        transfer_accounts(
+            program_id,
+            source,
+            destination,
+            owner,
+            &mints.ingot_token_program,
+            &ingot_mint,
+        ),
+    )
+}
+
+/// Creates a `CommitSmelt` instruction. `commitment` is
+/// `PendingSmelt::commitment_for(secret, user)`.
+///
+/// Accounts:
+/// 0. `[signer, writable]` User, pays for the pending smelt and escrows
+/// 1. `[writable]` User's ORE account
+/// 2. `[writable]` User's COAL account
+/// 3. `[]` Smelting state PDA
+/// 4. `[writable]` Pending smelt PDA
+/// 5. `[writable]` ORE escrow PDA
+/// 6. `[writable]` COAL escrow PDA
+/// 7. `[]` ORE mint
+/// 8. `[]` COAL mint
+/// 9. `[]` ORE token program
+/// 10. `[]` System program
+/// 11. `[]` COAL token program
+pub fn commit_smelt(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    ore_account: &Pubkey,
+    coal_account: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+    commitment: [u8; 32],
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (pending_smelt, _) = find_pending_smelt_address(program_id, user);
+    let (ore_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.ore_mint);
+    let (coal_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.coal_mint);
+    instruction(
+        program_id,
+        SmeltingInstruction::CommitSmelt { amount, commitment },
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(*ore_account, false),
+            AccountMeta::new(*coal_account, false),
+            AccountMeta::new_readonly(state, false),
+            AccountMeta::new(pending_smelt, false),
+            AccountMeta::new(ore_escrow, false),
+            AccountMeta::new(coal_escrow, false),
+            AccountMeta::new_readonly(mints.ore_mint, false),
+            AccountMeta::new_readonly(mints.coal_mint, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(mints.coal_token_program, false),
+        ],
+    )
+}
+
+/// Creates a `RevealSmelt` instruction.
+///
+/// Accounts:
+/// 0. `[signer, writable]` User, receives the pending smelt's rent
+/// 1. `[writable]` User's ORE account
+/// 2. `[writable]` User's INGOT account
+/// 3. `[writable]` Smelting state PDA
+/// 4. `[writable]` Pending smelt PDA
+/// 5. `[writable]` ORE escrow PDA
+/// 6. `[writable]` COAL escrow PDA
+/// 7. `[]` Forge authority PDA
+/// 8. `[writable]` ORE vault PDA
+/// 9. `[writable]` ORE mint, collects fees withheld in the escrow
+/// 10. `[writable]` COAL mint
+/// 11. `[writable]` INGOT mint PDA
+/// 12. `[]` ORE token program
+/// 13. `[]` COAL token program
+/// 14. `[]` INGOT token program
+/// 15. `[]` SlotHashes sysvar
+///
+/// A successful roll fails with `Slippage` when fewer than `min_ingot_out` INGOT
+/// would be minted, a failed one is unaffected.
+/// Per-user stats are recorded when the instruction goes through [`with_user_stats`].
+pub fn reveal_smelt(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    ore_account: &Pubkey,
+    ingot_account: &Pubkey,
+    mints: &ForgeMints,
+    secret: [u8; 32],
+    min_ingot_out: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    let (pending_smelt, _) = find_pending_smelt_address(program_id, user);
+    let (ore_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.ore_mint);
+    let (coal_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.coal_mint);
+    instruction(
+        program_id,
+        SmeltingInstruction::RevealSmelt {
+            secret,
+            min_ingot_out,
+        },
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(*ore_account, false),
+            AccountMeta::new(*ingot_account, false),
+            AccountMeta::new(state, false),
+            AccountMeta::new(pending_smelt, false),
+            AccountMeta::new(ore_escrow, false),
+            AccountMeta::new(coal_escrow, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new(mints.ore_mint, false),
+            AccountMeta::new(mints.coal_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new_readonly(mints.coal_token_program, false),
+            AccountMeta::new_readonly(mints.ingot_token_program, false),
+            AccountMeta::new_readonly(slot_hashes::id(), false),
+        ],
+    )
+}
+
+/// Creates a `ReclaimSmelt` instruction for an expired commitment.
+///
+/// Accounts:
+/// 0. `[signer, writable]` User, receives the pending smelt's rent
+/// 1. `[writable]` User's ORE account
+/// 2. `[]` Smelting state PDA
+/// 3. `[writable]` Pending smelt PDA
+/// 4. `[writable]` ORE escrow PDA
+/// 5. `[writable]` COAL escrow PDA
+/// 6. `[writable]` COAL mint
+/// 7. `[]` Forge authority PDA
+/// 8. `[]` ORE token program
+/// 9. `[writable]` ORE mint, collects fees withheld in the escrow
+/// 10. `[]` COAL token program
+pub fn reclaim_smelt(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    ore_account: &Pubkey,
+    mints: &ForgeMints,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (pending_smelt, _) = find_pending_smelt_address(program_id, user);
+    let (ore_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.ore_mint);
+    let (coal_escrow, _) = find_escrow_address(program_id, &pending_smelt, &mints.coal_mint);
+    instruction(
+        program_id,
+        SmeltingInstruction::ReclaimSmelt,
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(*ore_account, false),
+            AccountMeta::new_readonly(state, false),
+            AccountMeta::new(pending_smelt, false),
+            AccountMeta::new(ore_escrow, false),
+            AccountMeta::new(coal_escrow, false),
+            AccountMeta::new(mints.coal_mint, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new(mints.ore_mint, false),
+            AccountMeta::new_readonly(mints.coal_token_program, false),
+        ],
+    )
+}
+
+/// Creates an `UpdateConfig` instruction. Out-of-range values fail, a COAL cost
+/// has to burn some COAL without overflowing on the largest smelt.
+///
+/// Accounts:
+/// 0. `[signer]` Authority
+/// 1. `[writable]` Smelting state PDA
+pub fn update_config(program_id: &Pubkey, authority: &Pubkey, param: ConfigParam) -> Instruction {
+    admin_instruction(
+        program_id,
+        authority,
+        SmeltingInstruction::UpdateConfig { param },
+    )
+}
+
+/// Creates a `ProposeAuthority` instruction.
+///
+/// Accounts:
+/// 0. `[signer]` Authority
+/// 1. `[writable]` Smelting state PDA
+pub fn propose_authority(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    admin_instruction(
+        program_id,
+        authority,
+        SmeltingInstruction::ProposeAuthority {
+            new_authority: *new_authority,
+        },
+    )
+}
+
+/// Creates an `AcceptAuthority` instruction.
+///
+/// Accounts:
+/// 0. `[signer]` Pending authority
+/// 1. `[writable]` Smelting state PDA
+pub fn accept_authority(program_id: &Pubkey, new_authority: &Pubkey) -> Instruction {
+    admin_instruction(
+        program_id,
+        new_authority,
+        SmeltingInstruction::AcceptAuthority,
+    )
+}
+
+/// Creates a `SetPause` instruction. `paused_operations` is a mask of the
+/// `PAUSE_*` flags.
+///
+/// Accounts:
+/// 0. `[signer]` Authority
+/// 1. `[writable]` Smelting state PDA
+pub fn set_pause(program_id: &Pubkey, authority: &Pubkey, paused_operations: u8) -> Instruction {
+    admin_instruction(
+        program_id,
+        authority,
+        SmeltingInstruction::SetPause { paused_operations },
+    )
+}
+
+/// Creates a `WithdrawFees` instruction.
+///
+/// Accounts:
+/// 0. `[signer]` Authority or treasury
+/// 1. `[writable]` Smelting state PDA
+/// 2. `[writable]` ORE vault PDA
+/// 3. `[writable]` Destination ORE account
+/// 4. `[]` Forge authority PDA
+/// 5. `[]` ORE token program
+/// 6. `[]` ORE mint
+pub fn withdraw_fees(
+    program_id: &Pubkey,
+    withdraw_authority: &Pubkey,
+    destination: &Pubkey,
+    mints: &ForgeMints,
+    amount: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::WithdrawFees { amount },
+        vec![
+            AccountMeta::new_readonly(*withdraw_authority, true),
+            AccountMeta::new(state, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new_readonly(mints.ore_token_program, false),
+            AccountMeta::new_readonly(mints.ore_mint, false),
+        ],
+    )
+}
+
+/// Appends the user's stats PDA to a `Smelt`, `RevealSmelt` or `Unsmelt`
+/// instruction so the program records it. The user pays for the stats account
+/// the first time, so it becomes writable.
+///
+/// Extra accounts:
+/// 0. `[writable]` User stats PDA
+/// 1. `[]` System program
+pub fn with_user_stats(
+    mut instruction: Instruction,
+    program_id: &Pubkey,
+    user: &Pubkey,
+) -> Instruction {
+    let (user_stats, _) = find_user_stats_address(program_id, user);
+    if let Some(user_meta) = instruction
+        .accounts
+        .iter_mut()
+        .find(|meta| meta.pubkey == *user)
+    {
+        user_meta.is_writable = true;
+    }
+    instruction.accounts.extend([
+        AccountMeta::new(user_stats, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ]);
+    instruction
+}
+
+/// Creates a `CloseUserStats` instruction that returns the stats rent to the user.
+///
+/// Accounts:
+/// 0. `[signer, writable]` User
+/// 1. `[writable]` User stats PDA
+pub fn close_user_stats(program_id: &Pubkey, user: &Pubkey) -> Instruction {
+    let (user_stats, _) = find_user_stats_address(program_id, user);
+    instruction(
+        program_id,
+        SmeltingInstruction::CloseUserStats,
+        vec![
+            AccountMeta::new(*user, true),
+            AccountMeta::new(user_stats, false),
+        ],
+    )
+}
+
+/// Creates a `SetIngotMetadata` instruction. A Token-2022 INGOT keeps its
+/// metadata in the mint itself, an SPL Token INGOT gets a Metaplex metadata
+/// account. Either way the forge authority PDA is the update authority.
+///
+/// Accounts:
+/// 0. `[signer, writable]` Authority, pays for the metadata
+/// 1. `[]` Smelting state PDA
+/// 2. `[writable]` INGOT mint PDA
+/// 3. `[]` Forge authority PDA
+/// 4. `[]` INGOT token program
+/// 5. `[]` System program
+/// 6. `[writable]` Metaplex metadata PDA, SPL Token INGOT only
+/// 7. `[]` Metaplex token metadata program, SPL Token INGOT only
+pub fn set_ingot_metadata(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    mints: &ForgeMints,
+    name: String,
+    symbol: String,
+    uri: String,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let mut accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new_readonly(state, false),
+        AccountMeta::new(ingot_mint, false),
+        AccountMeta::new_readonly(forge_authority, false),
+        AccountMeta::new_readonly(mints.ingot_token_program, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    if mints.ingot_token_program != spl_token_2022::id() {
+        let (metadata, _) = metaplex::find_metadata_address(&ingot_mint);
+        accounts.extend([
+            AccountMeta::new(metadata, false),
+            AccountMeta::new_readonly(metaplex::ID, false),
+        ]);
+    }
+    instruction(
+        program_id,
+        SmeltingInstruction::SetIngotMetadata { name, symbol, uri },
+        accounts,
+    )
+}
+
+/// Creates a `MigrateState` instruction that rewrites the smelting state in
+/// the current layout, growing the account if needed.
+///
+/// Accounts:
+/// 0. `[signer, writable]` Authority, pays for any extra rent
+/// 1. `[writable]` Smelting state PDA
+/// 2. `[]` System program
+pub fn migrate_state(program_id: &Pubkey, authority: &Pubkey) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    instruction(
+        program_id,
+        SmeltingInstruction::MigrateState,
+        vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
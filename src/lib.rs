@@ -1,8 +1,17 @@
+#[cfg(feature = "client")]
+pub mod client;
 pub mod constants;
+#[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
 pub mod error;
+pub mod events;
+#[cfg(feature = "idl")]
+pub mod idl;
 pub mod instruction;
+pub mod math;
+pub mod metaplex;
 pub mod processor;
 pub mod state;
+pub mod validation;
 
-solana_program::declare_id!("Sme1t1ngProgramIDXXXXXXXXXXXXXXXXXXXXXXX");
+solana_program::declare_id!("Sme1tingProgram1111111111111111111111111111");
@@ -0,0 +1,33 @@
+//! Checked arithmetic for the forge ledger. Release builds turn overflow
+//! checks off, so every amount the forge accounts for goes through here and
+//! fails with `SmeltingError::MathOverflow` rather than wrapping or clamping.
+
+use solana_program::program_error::ProgramError;
+
+use crate::error::SmeltingError;
+
+fn overflow() -> ProgramError {
+    SmeltingError::MathOverflow.into()
+}
+
+pub fn add(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_add(b).ok_or_else(overflow)
+}
+
+pub fn sub(a: u64, b: u64) -> Result<u64, ProgramError> {
+    a.checked_sub(b).ok_or_else(overflow)
+}
+
+// Products are taken in u128, so two u64 amounts never overflow
+pub fn mul(a: u128, b: u128) -> Result<u128, ProgramError> {
+    a.checked_mul(b).ok_or_else(overflow)
+}
+
+pub fn to_u64(value: u128) -> Result<u64, ProgramError> {
+    u64::try_from(value).map_err(|_| overflow())
+}
+
+/// One whole token in base units.
+pub fn pow10(decimals: u8) -> Result<u128, ProgramError> {
+    10u128.checked_pow(decimals as u32).ok_or_else(overflow)
+}
@@ -0,0 +1,79 @@
+//! Just enough of the Metaplex Token Metadata interface to describe an SPL
+//! Token INGOT mint, without depending on the Metaplex crates. `tests/metaplex.rs`
+//! checks the encoding against `mpl-token-metadata`.
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+    system_program,
+};
+
+pub const ID: Pubkey = pubkey!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+
+pub const METADATA_SEED: &[u8] = b"metadata";
+const CREATE_METADATA_ACCOUNT_V3: u8 = 33;
+const UPDATE_METADATA_ACCOUNT_V2: u8 = 15;
+
+pub fn find_metadata_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[METADATA_SEED, ID.as_ref(), mint.as_ref()], &ID)
+}
+
+// Borsh `DataV2` with no seller fee, creators, collection or uses
+fn data_v2(name: &str, symbol: &str, uri: &str) -> Vec<u8> {
+    let mut data = borsh::to_vec(&(name, symbol, uri, 0u16)).expect("writing to a Vec cannot fail");
+    data.extend([0, 0, 0]);
+    data
+}
+
+/// `CreateMetadataAccountV3`, leaving the metadata mutable.
+#[allow(clippy::too_many_arguments)]
+pub fn create_metadata_account_v3(
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    mint_authority: &Pubkey,
+    payer: &Pubkey,
+    update_authority: &Pubkey,
+    name: &str,
+    symbol: &str,
+    uri: &str,
+) -> Instruction {
+    let mut data = vec![CREATE_METADATA_ACCOUNT_V3];
+    data.extend(data_v2(name, symbol, uri));
+    // is_mutable, then no collection details
+    data.extend([1, 0]);
+    Instruction {
+        program_id: ID,
+        accounts: vec![
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*mint_authority, true),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*update_authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    }
+}
+
+/// `UpdateMetadataAccountV2`, replacing the data and keeping everything else.
+pub fn update_metadata_account_v2(
+    metadata: &Pubkey,
+    update_authority: &Pubkey,
+    name: &str,
+    symbol: &str,
+    uri: &str,
+) -> Instruction {
+    let mut data = vec![UPDATE_METADATA_ACCOUNT_V2, 1];
+    data.extend(data_v2(name, symbol, uri));
+    // No new update authority, primary sale flag or mutability
+    data.extend([0, 0, 0]);
+    Instruction {
+        program_id: ID,
+        accounts: vec![
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*update_authority, true),
+        ],
+        data,
+    }
+}
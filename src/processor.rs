@@ -1,12 +1,32 @@
-use solana_program::hash::hash;
+use std::{cmp::Ordering, slice::Iter};
+
+use arrayref::{array_ref, array_refs};
+use solana_program::hash::hashv;
 
 use crate::{
-    constants::{AUTHORITY_SEED, MAX_AMOUNT, SMELTING_SUCCESS_RATE},
+    constants::{
+        AUTHORITY_SEED, BACKPOINTER_SEED, ESCROW_SEED, MAX_AMOUNT, MAX_DIFFICULTY,
+        MAX_INGOT_SUPPLY, MAX_METADATA_NAME_LEN, MAX_METADATA_SYMBOL_LEN, MAX_METADATA_URI_LEN,
+        MAX_UNSMELT_FEE_BPS, MIN_DIFFICULTY, ORE_VAULT_SEED, PAUSE_ALL, PAUSE_SMELT, PAUSE_UNSMELT,
+        PENDING_SMELT_SEED, SMELTING_SUCCESS_RATE, UNSMELT_FEE_PERCENTAGE, USER_STATS_SEED,
+        WRAPPED_MINT_SEED,
+    },
     error::SmeltingError,
-    instruction::SmeltingInstruction,
-    state::{SmeltingState, MAX_INGOT_SUPPLY},
+    events::{ConfigChange, ConfigChanged, SmeltFailed, SmeltSucceeded, SmeltingEvent, Unsmelted},
+    instruction::{ConfigParam, SmeltingInstruction},
+    math, metaplex,
+    state::{
+        find_escrow_address, find_forge_authority_address, find_ingot_mint_address,
+        find_ore_vault_address, find_pending_smelt_address, find_smelting_state_address,
+        find_user_stats_address, PendingSmelt, SmeltingState, UserStats, STATE_VERSION,
+    },
+    validation::{
+        validate_amount, validate_authority, validate_forge_authority, validate_mint,
+        validate_mint_account, validate_not_paused, validate_pda, validate_signer,
+        validate_slot_hashes, validate_smelting_state, validate_system_program,
+        validate_token_account, validate_token_program, validate_upgrade_authority, validate_vault,
+    },
 };
-use solana_program::program_error::ProgramError;
 
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
@@ -14,11 +34,24 @@ use solana_program::{
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
+    system_instruction, system_program,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use spl_token::state::Account as TokenAccount;
+use spl_token::amount_to_ui_amount_string_trimmed;
+use spl_token_2022::{
+    extension::{
+        metadata_pointer,
+        transfer_fee::{instruction::harvest_withheld_tokens_to_mint, TransferFeeAmount},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_metadata_interface::{
+    instruction as token_metadata,
+    state::{Field, TokenMetadata},
+};
 
 pub struct Processor;
 
@@ -31,341 +64,1961 @@ impl Processor {
         let instruction = SmeltingInstruction::unpack(instruction_data)?;
 
         match instruction {
-            SmeltingInstruction::Smelt { amount } => {
-                if amount == 0 || amount > MAX_AMOUNT {
-                    return Err(ProgramError::InvalidInstructionData.into());
-                }
-                Self::process_smelt(accounts, amount, program_id)
-            }
-            SmeltingInstruction::Unsmelt { amount } => {
+            SmeltingInstruction::Smelt {
+                amount,
+                nonce,
+                min_ingot_out,
+            } => {
                 if amount == 0 || amount > MAX_AMOUNT {
-                    return Err(ProgramError::InvalidInstructionData.into());
+                    return Err(SmeltingError::InvalidAmount.into());
                 }
-                Self::process_unsmelt(accounts, amount, program_id)
+                Self::process_smelt(accounts, amount, nonce, min_ingot_out, program_id)
             }
-            SmeltingInstruction::MintIngot { amount } => {
+            SmeltingInstruction::Unsmelt {
+                amount,
+                min_ore_out,
+            } => {
                 if amount == 0 || amount > MAX_AMOUNT {
-                    return Err(ProgramError::InvalidInstructionData.into());
+                    return Err(SmeltingError::InvalidAmount.into());
                 }
-                Self::process_mint_ingot(accounts, amount, program_id)
+                Self::process_unsmelt(accounts, amount, min_ore_out, program_id)
             }
             SmeltingInstruction::TransferOre { amount } => {
                 if amount == 0 || amount > MAX_AMOUNT {
-                    return Err(ProgramError::InvalidInstructionData.into());
+                    return Err(SmeltingError::InvalidAmount.into());
                 }
                 Self::process_transfer_ore(accounts, amount, program_id)
             }
             SmeltingInstruction::TransferIngot { amount } => {
                 if amount == 0 || amount > MAX_AMOUNT {
-                    return Err(ProgramError::InvalidInstructionData.into());
+                    return Err(SmeltingError::InvalidAmount.into());
                 }
                 Self::process_transfer_ingot(accounts, amount, program_id)
             }
+            SmeltingInstruction::Initialize {
+                ingot_decimals,
+                wrap_ratio_numerator,
+                wrap_ratio_denominator,
+            } => {
+                if wrap_ratio_numerator == 0 || wrap_ratio_denominator == 0 {
+                    return Err(SmeltingError::InvalidWrapRatio.into());
+                }
+                Self::process_initialize(
+                    accounts,
+                    ingot_decimals,
+                    wrap_ratio_numerator,
+                    wrap_ratio_denominator,
+                    program_id,
+                )
+            }
+            SmeltingInstruction::CommitSmelt { amount, commitment } => {
+                if amount == 0 || amount > MAX_AMOUNT {
+                    return Err(SmeltingError::InvalidAmount.into());
+                }
+                Self::process_commit_smelt(accounts, amount, commitment, program_id)
+            }
+            SmeltingInstruction::RevealSmelt {
+                secret,
+                min_ingot_out,
+            } => Self::process_reveal_smelt(accounts, secret, min_ingot_out, program_id),
+            SmeltingInstruction::ReclaimSmelt => Self::process_reclaim_smelt(accounts, program_id),
+            SmeltingInstruction::UpdateConfig { param } => {
+                Self::process_update_config(accounts, param, program_id)
+            }
+            SmeltingInstruction::ProposeAuthority { new_authority } => {
+                Self::process_propose_authority(accounts, new_authority, program_id)
+            }
+            SmeltingInstruction::AcceptAuthority => {
+                Self::process_accept_authority(accounts, program_id)
+            }
+            SmeltingInstruction::SetPause { paused_operations } => {
+                if paused_operations & !PAUSE_ALL != 0 {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                Self::process_set_pause(accounts, paused_operations, program_id)
+            }
+            SmeltingInstruction::WithdrawFees { amount } => {
+                if amount == 0 {
+                    return Err(SmeltingError::InvalidAmount.into());
+                }
+                Self::process_withdraw_fees(accounts, amount, program_id)
+            }
+            SmeltingInstruction::CloseUserStats => {
+                Self::process_close_user_stats(accounts, program_id)
+            }
+            SmeltingInstruction::MigrateState => Self::process_migrate_state(accounts, program_id),
+            SmeltingInstruction::SetIngotMetadata { name, symbol, uri } => {
+                if name.len() > MAX_METADATA_NAME_LEN
+                    || symbol.len() > MAX_METADATA_SYMBOL_LEN
+                    || uri.len() > MAX_METADATA_URI_LEN
+                {
+                    return Err(SmeltingError::MetadataTooLong.into());
+                }
+                Self::process_set_ingot_metadata(accounts, name, symbol, uri, program_id)
+            }
         }
     }
 
-    fn process_smelt(accounts: &[AccountInfo], amount: u64, program_id: &Pubkey) -> ProgramResult {
+    fn process_initialize(
+        accounts: &[AccountInfo],
+        ingot_decimals: u8,
+        wrap_ratio_numerator: u64,
+        wrap_ratio_denominator: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        let user_account = next_account_info(account_info_iter)?;
-        let ore_account = next_account_info(account_info_iter)?;
-        let coal_account = next_account_info(account_info_iter)?;
-        let ingot_account = next_account_info(account_info_iter)?;
+        let authority_account = next_account_info(account_info_iter)?;
         let smelting_state_account = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let coal_mint = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+        let ore_vault = next_account_info(account_info_iter)?;
         let token_program = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let program_data = next_account_info(account_info_iter)?;
 
-        let mut smelting_state = SmeltingState::unpack(&smelting_state_account.data.borrow())?;
+        validate_signer(authority_account)?;
+        validate_upgrade_authority(program_data, authority_account, program_id)?;
+        validate_token_program(token_program, None)?;
+        validate_system_program(system_program_account)?;
 
-        // Check if user has enough ORE tokens
-        let ore_account_data = TokenAccount::unpack(&ore_account.data.borrow())?;
-        if ore_account_data.amount < amount {
-            return Err(SmeltingError::InsufficientBalance.into());
+        // Derive every PDA the forge owns and make sure the caller passed the right ones
+        let (state_key, state_bump) = find_smelting_state_address(program_id);
+        let (authority_key, authority_bump) = find_forge_authority_address(program_id);
+        let (ingot_mint_key, ingot_mint_bump) = find_ingot_mint_address(program_id);
+        let (ore_vault_key, ore_vault_bump) = find_ore_vault_address(program_id);
+
+        validate_pda(smelting_state_account, &state_key)?;
+        validate_pda(forge_authority, &authority_key)?;
+        validate_pda(ingot_mint, &ingot_mint_key)?;
+        validate_pda(ore_vault, &ore_vault_key)?;
+        if !smelting_state_account.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
         }
 
-        // Check if smelting succeeds (80% chance)
-        let clock = Clock::get()?;
-        let seed = hash(&clock.slot.to_le_bytes()).to_bytes();
-        let success = (seed[0] as u16) < ((SMELTING_SUCCESS_RATE as u16 * 256) / 100);
+        // Read decimals from the real ORE and COAL mints
+        let ore_mint_data = validate_mint_account(ore_mint)?;
+        let coal_mint_data = validate_mint_account(coal_mint)?;
+        validate_token_program(ore_token_program, Some(ore_mint.owner))?;
 
-        // Precompute amounts
-        let coal_amount = amount;
-        let ore_amount = amount;
-        let ingot_amount = amount;
+        let rent = Rent::get()?;
+        let clock = Clock::get()?;
 
-        // Burn COAL tokens
-        invoke(
-            &spl_token::instruction::burn(
-                token_program.key,
-                coal_account.key,
-                smelting_state.coal_mint,
-                user_account.key,
-                &[],
-                coal_amount,
-            )?,
-            &[coal_account, user_account, token_program],
+        // Allocate the smelting state PDA
+        Self::create_pda_account(
+            authority_account,
+            smelting_state_account,
+            SmeltingState::LEN,
+            program_id,
+            system_program_account,
+            &[BACKPOINTER_SEED, &[state_bump]],
+            &rent,
         )?;
 
-        if success {
-            // Check if minting more INGOT tokens would exceed the maximum supply
-            if !smelting_state.can_mint_ingot(ingot_amount) {
-                return Err(SmeltingError::MaxSupplyExceeded.into());
-            }
-
-            // Transfer ORE tokens to program account
+        // Create the INGOT mint with the forge authority PDA as mint authority. A Token-2022
+        // INGOT points at itself for metadata, which SetIngotMetadata fills in later
+        let ingot_extensions = if *token_program.key == spl_token_2022::id() {
+            vec![ExtensionType::MetadataPointer]
+        } else {
+            vec![]
+        };
+        Self::create_pda_account(
+            authority_account,
+            ingot_mint,
+            ExtensionType::try_calculate_account_len::<Mint>(&ingot_extensions)?,
+            token_program.key,
+            system_program_account,
+            &[WRAPPED_MINT_SEED, &[ingot_mint_bump]],
+            &rent,
+        )?;
+        if !ingot_extensions.is_empty() {
             invoke(
-                &spl_token::instruction::transfer(
-                    token_program.key,
-                    ore_account.key,
-                    smelting_state.ore_vault,
-                    user_account.key,
-                    &[],
-                    ore_amount,
-                )?,
-                &[ore_account, user_account, token_program],
-            )?;
-
-            // Mint INGOT tokens to user
-            invoke_signed(
-                &spl_token::instruction::mint_to(
+                &metadata_pointer::instruction::initialize(
                     token_program.key,
-                    smelting_state.ingot_mint,
-                    ingot_account.key,
-                    &smelting_state.authority,
-                    &[],
-                    ingot_amount,
+                    ingot_mint.key,
+                    Some(*forge_authority.key),
+                    Some(*ingot_mint.key),
                 )?,
-                &[ingot_account, token_program],
-                &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
+                &[ingot_mint.clone(), token_program.clone()],
             )?;
+        }
+        invoke(
+            &spl_token_2022::instruction::initialize_mint2(
+                token_program.key,
+                ingot_mint.key,
+                forge_authority.key,
+                None,
+                ingot_decimals,
+            )?,
+            &[ingot_mint.clone(), token_program.clone()],
+        )?;
 
-            smelting_state.update_on_successful_smelt(amount)?;
+        // Create the ORE vault owned by the forge authority PDA
+        Self::create_token_account(
+            authority_account,
+            ore_vault,
+            ore_mint,
+            forge_authority.key,
+            ore_token_program,
+            system_program_account,
+            &[ORE_VAULT_SEED, &[ore_vault_bump]],
+            &rent,
+        )?;
 
-            msg!("Successfully smelted {} ORE into INGOT", amount);
-        } else {
-            msg!("Smelting failed. COAL burned but no INGOT produced");
+        // Every later instruction checks the mints against these decimals
+        let ingot_mint_data = validate_mint_account(ingot_mint)?;
+        if ingot_mint_data.decimals != ingot_decimals {
+            return Err(SmeltingError::DecimalsMismatch.into());
         }
 
+        let smelting_state = SmeltingState {
+            is_initialized: 1,
+            authority: *authority_account.key,
+            authority_bump,
+            ore_mint: *ore_mint.key,
+            ingot_mint: *ingot_mint.key,
+            coal_mint: *coal_mint.key,
+            ore_vault: *ore_vault.key,
+            total_ingots_minted: 0,
+            total_ore_locked: 0,
+            ore_decimals: ore_mint_data.decimals,
+            ingot_decimals: ingot_mint_data.decimals,
+            coal_decimals: coal_mint_data.decimals,
+            difficulty: MIN_DIFFICULTY,
+            challenge: hashv(&[
+                smelting_state_account.key.as_ref(),
+                &clock.slot.to_le_bytes(),
+            ])
+            .to_bytes(),
+            wrap_ratio_numerator,
+            wrap_ratio_denominator,
+            // Default to one whole COAL per whole ORE
+            coal_per_ore: math::to_u64(math::pow10(coal_mint_data.decimals)?)?,
+            coal_base_cost: 0,
+            pending_authority: Pubkey::default(),
+            success_rate: SMELTING_SUCCESS_RATE,
+            unsmelt_fee_bps: UNSMELT_FEE_PERCENTAGE as u16 * 100,
+            max_ingot_supply: MAX_INGOT_SUPPLY,
+            max_amount: MAX_AMOUNT,
+            paused_operations: 0,
+            total_fees_accrued: 0,
+            treasury: Pubkey::default(),
+            ore_token_program: *ore_mint.owner,
+            coal_token_program: *coal_mint.owner,
+            ingot_token_program: *token_program.key,
+            reserved: [0; 126],
+        };
+
         SmeltingState::pack(
             smelting_state,
             &mut smelting_state_account.data.borrow_mut(),
         )?;
 
+        msg!("Forge initialized with INGOT mint {}", ingot_mint.key);
+
         Ok(())
     }
 
-    fn process_unsmelt(
+    fn process_update_config(
         accounts: &[AccountInfo],
-        amount: u64,
+        param: ConfigParam,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        let user_account = next_account_info(account_info_iter)?;
-        let ore_account = next_account_info(account_info_iter)?;
-        let ingot_account = next_account_info(account_info_iter)?;
+        let authority_account = next_account_info(account_info_iter)?;
         let smelting_state_account = next_account_info(account_info_iter)?;
-        let token_program = next_account_info(account_info_iter)?;
 
-        let mut smelting_state = SmeltingState::unpack(&smelting_state_account.data.borrow())?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_authority(authority_account, &smelting_state)?;
 
-        // Check if user has enough INGOT tokens
-        let ingot_account_data = TokenAccount::unpack(&ingot_account.data.borrow())?;
-        if ingot_account_data.amount < amount {
-            return Err(SmeltingError::InsufficientBalance.into());
+        let change = ConfigChange::Param(param.clone());
+        match param {
+            ConfigParam::SuccessRate(success_rate) => {
+                if success_rate == 0 || success_rate > 100 {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                msg!(
+                    "Success rate changed from {}% to {}%",
+                    smelting_state.success_rate,
+                    success_rate
+                );
+                smelting_state.success_rate = success_rate;
+            }
+            ConfigParam::UnsmeltFeeBps(unsmelt_fee_bps) => {
+                if unsmelt_fee_bps > MAX_UNSMELT_FEE_BPS {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                msg!(
+                    "Unsmelt fee changed from {} bps to {} bps",
+                    smelting_state.unsmelt_fee_bps,
+                    unsmelt_fee_bps
+                );
+                smelting_state.unsmelt_fee_bps = unsmelt_fee_bps;
+            }
+            ConfigParam::MaxIngotSupply(max_ingot_supply) => {
+                if max_ingot_supply == 0 || max_ingot_supply < smelting_state.total_ingots_minted {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                msg!(
+                    "Max INGOT supply changed from {} to {}",
+                    smelting_state.max_ingot_supply,
+                    max_ingot_supply
+                );
+                smelting_state.max_ingot_supply = max_ingot_supply;
+            }
+            ConfigParam::MaxAmount(max_amount) => {
+                if max_amount == 0 || max_amount > MAX_AMOUNT {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                msg!(
+                    "Max amount changed from {} to {}",
+                    smelting_state.max_amount,
+                    max_amount
+                );
+                smelting_state.max_amount = max_amount;
+            }
+            ConfigParam::WrapRatio {
+                numerator,
+                denominator,
+            } => {
+                if numerator == 0 || denominator == 0 {
+                    return Err(SmeltingError::InvalidWrapRatio.into());
+                }
+                // Outstanding INGOT was minted at the old ratio and must stay redeemable
+                if smelting_state.total_ingots_minted != 0 || smelting_state.total_ore_locked != 0 {
+                    return Err(SmeltingError::WrapRatioLocked.into());
+                }
+                msg!(
+                    "Wrap ratio changed from {}:{} to {}:{}",
+                    smelting_state.wrap_ratio_numerator,
+                    smelting_state.wrap_ratio_denominator,
+                    numerator,
+                    denominator
+                );
+                smelting_state.wrap_ratio_numerator = numerator;
+                smelting_state.wrap_ratio_denominator = denominator;
+            }
+            ConfigParam::Treasury(treasury) => {
+                msg!(
+                    "Treasury changed from {} to {}",
+                    smelting_state.treasury,
+                    treasury
+                );
+                smelting_state.treasury = treasury;
+            }
+            ConfigParam::Difficulty(difficulty) => {
+                if !(MIN_DIFFICULTY..=MAX_DIFFICULTY).contains(&difficulty) {
+                    return Err(SmeltingError::InvalidDifficulty.into());
+                }
+                msg!(
+                    "Difficulty changed from {} to {} bits",
+                    smelting_state.difficulty,
+                    difficulty
+                );
+                smelting_state.difficulty = difficulty;
+            }
+            ConfigParam::CoalCost {
+                coal_per_ore,
+                coal_base_cost,
+            } => {
+                // Smelting has to burn some COAL, and never so much that the cost of the
+                // largest amount a smelt can take overflows
+                if coal_per_ore == 0 && coal_base_cost == 0 {
+                    return Err(SmeltingError::InvalidConfig.into());
+                }
+                smelting_state.coal_per_ore = coal_per_ore;
+                smelting_state.coal_base_cost = coal_base_cost;
+                smelting_state
+                    .coal_cost(MAX_AMOUNT)
+                    .map_err(|_| SmeltingError::InvalidConfig)?;
+                msg!(
+                    "COAL cost changed to {} COAL per ORE plus {} COAL per smelt",
+                    amount_to_ui_amount_string_trimmed(coal_per_ore, smelting_state.coal_decimals),
+                    amount_to_ui_amount_string_trimmed(
+                        coal_base_cost,
+                        smelting_state.coal_decimals
+                    )
+                );
+            }
         }
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: *authority_account.key,
+            change,
+        })
+        .emit();
 
-        let fee = SmeltingState::calculate_unsmelt_fee(amount);
-        let ore_to_return = amount.saturating_sub(fee);
+        Ok(())
+    }
 
-        // Burn INGOT tokens
-        let burn_instruction = spl_token::instruction::burn(
-            token_program.key,
-            ingot_account.key,
-            smelting_state.ingot_mint,
-            user_account.key,
-            &[],
-            amount,
-        )?;
-        invoke(
-            &burn_instruction,
-            &[
-                ingot_account.clone(),
-                user_account.clone(),
-                token_program.clone(),
-            ],
-        )?;
+    fn process_propose_authority(
+        accounts: &[AccountInfo],
+        new_authority: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
 
-        // Transfer ORE tokens from vault to user
-        let transfer_instruction = spl_token::instruction::transfer(
-            token_program.key,
-            smelting_state.ore_vault,
-            ore_account.key,
-            &smelting_state.authority,
-            &[],
-            ore_to_return,
-        )?;
-        invoke_signed(
-            &transfer_instruction,
-            &[ore_account.clone(), token_program.clone()],
-            &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
-        )?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_authority(authority_account, &smelting_state)?;
 
-        smelting_state.update_on_unsmelt(amount, fee);
+        // Proposing the default pubkey cancels a pending transfer
+        smelting_state.pending_authority = new_authority;
 
-        SmeltingState::pack(
-            smelting_state,
-            &mut smelting_state_account.data.borrow_mut(),
-        )?;
+        msg!("Proposed {} as the new forge authority", new_authority);
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: *authority_account.key,
+            change: ConfigChange::AuthorityProposed(new_authority),
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_accept_authority(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let new_authority_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+
+        validate_signer(new_authority_account)?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        if smelting_state.pending_authority == Pubkey::default()
+            || *new_authority_account.key != smelting_state.pending_authority
+        {
+            return Err(SmeltingError::InvalidAuthority.into());
+        }
 
         msg!(
-            "Successfully unsmelted {} INGOT into {} ORE with a fee of {} ORE",
-            amount,
-            ore_to_return,
-            fee
+            "Forge authority transferred from {} to {}",
+            smelting_state.authority,
+            smelting_state.pending_authority
         );
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: smelting_state.pending_authority,
+            change: ConfigChange::AuthorityAccepted {
+                previous_authority: smelting_state.authority,
+            },
+        })
+        .emit();
+        smelting_state.authority = smelting_state.pending_authority;
+        smelting_state.pending_authority = Pubkey::default();
 
         Ok(())
     }
 
-    fn process_mint_ingot(
+    fn process_set_pause(
         accounts: &[AccountInfo],
-        amount: u64,
+        paused_operations: u8,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        let mint_authority = next_account_info(account_info_iter)?;
-        let ingot_mint = next_account_info(account_info_iter)?;
-        let ingot_account = next_account_info(account_info_iter)?;
+        let authority_account = next_account_info(account_info_iter)?;
         let smelting_state_account = next_account_info(account_info_iter)?;
-        let token_program = next_account_info(account_info_iter)?;
-
-        let mut smelting_state = SmeltingState::unpack(&smelting_state_account.data.borrow())?;
-
-        if !mint_authority.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
-        }
-
-        if *mint_authority.key != smelting_state.authority {
-            return Err(ProgramError::InvalidAccountData);
-        }
-
-        smelting_state.total_ingots_minted += amount;
-        if smelting_state.total_ingots_minted > MAX_INGOT_SUPPLY {
-            return Err(SmeltingError::MaxSupplyExceeded.into());
-        }
 
-        let mint_instruction = spl_token::instruction::mint_to(
-            token_program.key,
-            ingot_mint.key,
-            ingot_account.key,
-            mint_authority.key,
-            &[],
-            amount,
-        )?;
-        invoke_signed(
-            &mint_instruction,
-            &[
-                ingot_mint.clone(),
-                ingot_account.clone(),
-                mint_authority.clone(),
-                token_program.clone(),
-            ],
-            &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
-        )?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_authority(authority_account, &smelting_state)?;
 
-        msg!("Successfully minted {} INGOT", amount);
+        smelting_state.paused_operations = paused_operations;
 
-        SmeltingState::pack(
-            smelting_state,
-            &mut smelting_state_account.data.borrow_mut(),
-        )?;
+        msg!(
+            "Paused operations set to {:#04b} (smelt, unsmelt: {}, {})",
+            paused_operations,
+            paused_operations & PAUSE_SMELT != 0,
+            paused_operations & PAUSE_UNSMELT != 0
+        );
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: *authority_account.key,
+            change: ConfigChange::PausedOperations(paused_operations),
+        })
+        .emit();
 
         Ok(())
     }
 
-    fn process_transfer_ore(
+    fn process_withdraw_fees(
         accounts: &[AccountInfo],
         amount: u64,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        let source_account = next_account_info(account_info_iter)?;
+        let withdraw_authority = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let ore_vault = next_account_info(account_info_iter)?;
         let destination_account = next_account_info(account_info_iter)?;
-        let authority = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
         let token_program = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
 
-        if !authority.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
+        validate_signer(withdraw_authority)?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(token_program, Some(&smelting_state.ore_token_program))?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        let is_treasury = smelting_state.treasury != Pubkey::default()
+            && *withdraw_authority.key == smelting_state.treasury;
+        if !is_treasury {
+            validate_authority(withdraw_authority, &smelting_state)?;
         }
+        validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+        validate_vault(ore_vault, &smelting_state)?;
 
-        // Check if source account has enough ORE tokens
-        let source_account_data = TokenAccount::unpack(&source_account.data.borrow())?;
-        if source_account_data.amount < amount {
-            return Err(SmeltingError::InsufficientBalance.into());
-        }
+        // Once a treasury is configured, fees can only be paid out to it
+        let treasury =
+            (smelting_state.treasury != Pubkey::default()).then_some(smelting_state.treasury);
+        validate_token_account(
+            destination_account,
+            &smelting_state.ore_mint,
+            treasury.as_ref(),
+        )?;
 
-        let transfer_instruction = spl_token::instruction::transfer(
-            token_program.key,
-            source_account.key,
-            destination_account.key,
-            authority.key,
-            &[],
+        smelting_state.update_on_fee_withdrawal(amount)?;
+
+        Self::transfer_tokens(
+            token_program,
+            ore_vault,
+            ore_mint,
+            destination_account,
+            forge_authority,
             amount,
-        )?;
-        invoke(
-            &transfer_instruction,
-            &[
-                source_account.clone(),
-                destination_account.clone(),
-                authority.clone(),
-                token_program.clone(),
-            ],
+            smelting_state.ore_decimals,
+            &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
         )?;
 
-        msg!("Successfully transferred {} ORE", amount);
+        msg!("Withdrew {} ORE of unsmelt fees", amount);
 
         Ok(())
     }
 
-    fn process_transfer_ingot(
+    fn process_smelt(
         accounts: &[AccountInfo],
         amount: u64,
+        nonce: u64,
+        min_ingot_out: u64,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
-        let source_account = next_account_info(account_info_iter)?;
-        let destination_account = next_account_info(account_info_iter)?;
-        let authority = next_account_info(account_info_iter)?;
-        let token_program = next_account_info(account_info_iter)?;
+        let user_account = next_account_info(account_info_iter)?;
+        let ore_account = next_account_info(account_info_iter)?;
+        let coal_account = next_account_info(account_info_iter)?;
+        let ingot_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let ore_vault = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let coal_mint = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let coal_token_program = next_account_info(account_info_iter)?;
+        let ingot_token_program = next_account_info(account_info_iter)?;
 
-        if !authority.is_signer {
-            return Err(ProgramError::MissingRequiredSignature);
-        }
+        validate_signer(user_account)?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(ore_token_program, Some(&smelting_state.ore_token_program))?;
+        validate_token_program(coal_token_program, Some(&smelting_state.coal_token_program))?;
+        validate_token_program(
+            ingot_token_program,
+            Some(&smelting_state.ingot_token_program),
+        )?;
+        validate_not_paused(&smelting_state, PAUSE_SMELT)?;
+        validate_amount(amount, &smelting_state)?;
+        let forge_authority_key =
+            validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_mint(
+            coal_mint,
+            &smelting_state.coal_mint,
+            smelting_state.coal_decimals,
+        )?;
+        validate_mint(
+            ingot_mint,
+            &smelting_state.ingot_mint,
+            smelting_state.ingot_decimals,
+        )?;
+        validate_vault(ore_vault, &smelting_state)?;
+        validate_token_account(
+            coal_account,
+            &smelting_state.coal_mint,
+            Some(user_account.key),
+        )?;
+        validate_token_account(ingot_account, &smelting_state.ingot_mint, None)?;
 
-        // Check if source account has enough INGOT tokens
-        let source_account_data = TokenAccount::unpack(&source_account.data.borrow())?;
-        if source_account_data.amount < amount {
+        // Check if user has enough ORE tokens
+        let ore_account_data = validate_token_account(
+            ore_account,
+            &smelting_state.ore_mint,
+            Some(user_account.key),
+        )?;
+        if ore_account_data.amount < amount {
             return Err(SmeltingError::InsufficientBalance.into());
         }
 
-        let transfer_instruction = spl_token::instruction::transfer(
-            token_program.key,
-            source_account.key,
-            destination_account.key,
-            authority.key,
+        // Check the proof of work against the current challenge
+        let proof = smelting_state.proof_hash(user_account.key, nonce);
+        if !SmeltingState::meets_difficulty(&proof, smelting_state.difficulty) {
+            return Err(SmeltingError::InsufficientDifficulty.into());
+        }
+
+        // Precompute amounts
+        let (ingot_amount, ore_amount) = smelting_state.ore_to_ingot(amount)?;
+        if ingot_amount == 0 {
+            return Err(SmeltingError::AmountTooSmall.into());
+        }
+        let coal_amount = smelting_state.coal_cost(ore_amount)?;
+
+        // Burn COAL tokens
+        invoke(
+            &spl_token_2022::instruction::burn_checked(
+                coal_token_program.key,
+                coal_account.key,
+                &smelting_state.coal_mint,
+                user_account.key,
+                &[],
+                coal_amount,
+                smelting_state.coal_decimals,
+            )?,
+            &[
+                coal_account.clone(),
+                coal_mint.clone(),
+                user_account.clone(),
+                coal_token_program.clone(),
+            ],
+        )?;
+
+        // Transfer ORE tokens to program account
+        let ore_amount = Self::transfer_tokens(
+            ore_token_program,
+            ore_account,
+            ore_mint,
+            ore_vault,
+            user_account,
+            ore_amount,
+            smelting_state.ore_decimals,
             &[],
-            amount,
         )?;
-        invoke(
-            &transfer_instruction,
+
+        // A transfer fee may have shaved the deposit, so only what arrived backs INGOT
+        let (ingot_amount, _) = smelting_state.ore_to_ingot(ore_amount)?;
+        if ingot_amount == 0 {
+            return Err(SmeltingError::AmountTooSmall.into());
+        }
+        if ingot_amount < min_ingot_out {
+            return Err(SmeltingError::Slippage.into());
+        }
+
+        // Check if minting more INGOT tokens would exceed the maximum supply
+        if !smelting_state.can_mint_ingot(ingot_amount)? {
+            return Err(SmeltingError::MaxSupplyExceeded.into());
+        }
+
+        // Mint INGOT tokens to user
+        invoke_signed(
+            &spl_token_2022::instruction::mint_to_checked(
+                ingot_token_program.key,
+                &smelting_state.ingot_mint,
+                ingot_account.key,
+                &forge_authority_key,
+                &[],
+                ingot_amount,
+                smelting_state.ingot_decimals,
+            )?,
             &[
-                source_account.clone(),
-                destination_account.clone(),
-                authority.clone(),
-                token_program.clone(),
+                ingot_mint.clone(),
+                ingot_account.clone(),
+                forge_authority.clone(),
+                ingot_token_program.clone(),
             ],
+            &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
         )?;
 
-        msg!("Successfully transferred {} INGOT", amount);
+        smelting_state.update_on_successful_smelt(ore_amount, ingot_amount)?;
+        smelting_state.rotate_challenge(&proof);
+
+        msg!(
+            "Successfully smelted {} ORE into {} INGOT",
+            ore_amount,
+            ingot_amount
+        );
+        SmeltingEvent::SmeltSucceeded(SmeltSucceeded {
+            user: *user_account.key,
+            ore_amount,
+            ingot_amount,
+            coal_burned: coal_amount,
+        })
+        .emit();
+
+        Self::update_user_stats(account_info_iter, user_account, program_id, |user_stats| {
+            user_stats.record_smelt(ore_amount, ingot_amount, coal_amount)
+        })?;
 
         Ok(())
     }
+
+    fn process_commit_smelt(
+        accounts: &[AccountInfo],
+        amount: u64,
+        commitment: [u8; 32],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_account = next_account_info(account_info_iter)?;
+        let ore_account = next_account_info(account_info_iter)?;
+        let coal_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let pending_smelt_account = next_account_info(account_info_iter)?;
+        let ore_escrow = next_account_info(account_info_iter)?;
+        let coal_escrow = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let coal_mint = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+        let coal_token_program = next_account_info(account_info_iter)?;
+
+        validate_signer(user_account)?;
+        validate_system_program(system_program_account)?;
+        let smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(ore_token_program, Some(&smelting_state.ore_token_program))?;
+        validate_token_program(coal_token_program, Some(&smelting_state.coal_token_program))?;
+        validate_not_paused(&smelting_state, PAUSE_SMELT)?;
+        validate_amount(amount, &smelting_state)?;
+        let forge_authority_key = smelting_state.forge_authority(program_id)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_mint(
+            coal_mint,
+            &smelting_state.coal_mint,
+            smelting_state.coal_decimals,
+        )?;
+        validate_token_account(
+            coal_account,
+            &smelting_state.coal_mint,
+            Some(user_account.key),
+        )?;
+
+        let (pending_smelt_key, bump) = find_pending_smelt_address(program_id, user_account.key);
+        let (ore_escrow_key, ore_escrow_bump) =
+            find_escrow_address(program_id, &pending_smelt_key, &smelting_state.ore_mint);
+        let (coal_escrow_key, coal_escrow_bump) =
+            find_escrow_address(program_id, &pending_smelt_key, &smelting_state.coal_mint);
+        validate_pda(pending_smelt_account, &pending_smelt_key)?;
+        validate_pda(ore_escrow, &ore_escrow_key)?;
+        validate_pda(coal_escrow, &coal_escrow_key)?;
+        if !pending_smelt_account.data_is_empty() {
+            return Err(SmeltingError::PendingSmeltExists.into());
+        }
+
+        // Check if user has enough ORE tokens
+        let ore_account_data = validate_token_account(
+            ore_account,
+            &smelting_state.ore_mint,
+            Some(user_account.key),
+        )?;
+        if ore_account_data.amount < amount {
+            return Err(SmeltingError::InsufficientBalance.into());
+        }
+
+        // Precompute amounts
+        let (ingot_amount, ore_used) = smelting_state.ore_to_ingot(amount)?;
+        if ingot_amount == 0 {
+            return Err(SmeltingError::AmountTooSmall.into());
+        }
+        let coal_cost = smelting_state.coal_cost(ore_used)?;
+
+        let rent = Rent::get()?;
+        let clock = Clock::get()?;
+
+        // Allocate the pending smelt PDA and its escrow token accounts
+        Self::create_pda_account(
+            user_account,
+            pending_smelt_account,
+            PendingSmelt::LEN,
+            program_id,
+            system_program_account,
+            &[PENDING_SMELT_SEED, user_account.key.as_ref(), &[bump]],
+            &rent,
+        )?;
+        Self::create_token_account(
+            user_account,
+            ore_escrow,
+            ore_mint,
+            &forge_authority_key,
+            ore_token_program,
+            system_program_account,
+            &[
+                ESCROW_SEED,
+                pending_smelt_key.as_ref(),
+                smelting_state.ore_mint.as_ref(),
+                &[ore_escrow_bump],
+            ],
+            &rent,
+        )?;
+        Self::create_token_account(
+            user_account,
+            coal_escrow,
+            coal_mint,
+            &forge_authority_key,
+            coal_token_program,
+            system_program_account,
+            &[
+                ESCROW_SEED,
+                pending_smelt_key.as_ref(),
+                smelting_state.coal_mint.as_ref(),
+                &[coal_escrow_bump],
+            ],
+            &rent,
+        )?;
+
+        // Escrow ORE and COAL tokens until the reveal, recording what arrived
+        // after any transfer fee
+        let ore_amount = Self::transfer_tokens(
+            ore_token_program,
+            ore_account,
+            ore_mint,
+            ore_escrow,
+            user_account,
+            amount,
+            smelting_state.ore_decimals,
+            &[],
+        )?;
+        let coal_amount = Self::transfer_tokens(
+            coal_token_program,
+            coal_account,
+            coal_mint,
+            coal_escrow,
+            user_account,
+            coal_cost,
+            smelting_state.coal_decimals,
+            &[],
+        )?;
+        if smelting_state.ore_to_ingot(ore_amount)?.0 == 0 {
+            return Err(SmeltingError::AmountTooSmall.into());
+        }
+
+        let pending_smelt = PendingSmelt {
+            is_initialized: true,
+            user: *user_account.key,
+            ore_amount,
+            coal_amount,
+            commitment,
+            commit_slot: clock.slot,
+            bump,
+            ore_escrow_bump,
+            coal_escrow_bump,
+        };
+        PendingSmelt::pack(pending_smelt, &mut pending_smelt_account.data.borrow_mut())?;
+
+        msg!(
+            "Committed {} ORE for smelting at slot {}",
+            ore_amount,
+            clock.slot
+        );
+
+        Ok(())
+    }
+
+    fn process_reveal_smelt(
+        accounts: &[AccountInfo],
+        secret: [u8; 32],
+        min_ingot_out: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_account = next_account_info(account_info_iter)?;
+        let ore_account = next_account_info(account_info_iter)?;
+        let ingot_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let pending_smelt_account = next_account_info(account_info_iter)?;
+        let ore_escrow = next_account_info(account_info_iter)?;
+        let coal_escrow = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let ore_vault = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let coal_mint = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let coal_token_program = next_account_info(account_info_iter)?;
+        let ingot_token_program = next_account_info(account_info_iter)?;
+        let slot_hashes_account = next_account_info(account_info_iter)?;
+
+        validate_signer(user_account)?;
+        validate_slot_hashes(slot_hashes_account)?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(ore_token_program, Some(&smelting_state.ore_token_program))?;
+        validate_token_program(coal_token_program, Some(&smelting_state.coal_token_program))?;
+        validate_token_program(
+            ingot_token_program,
+            Some(&smelting_state.ingot_token_program),
+        )?;
+        validate_not_paused(&smelting_state, PAUSE_SMELT)?;
+        let forge_authority_key =
+            validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_mint(
+            coal_mint,
+            &smelting_state.coal_mint,
+            smelting_state.coal_decimals,
+        )?;
+        validate_mint(
+            ingot_mint,
+            &smelting_state.ingot_mint,
+            smelting_state.ingot_decimals,
+        )?;
+        validate_vault(ore_vault, &smelting_state)?;
+        validate_token_account(ore_account, &smelting_state.ore_mint, None)?;
+        validate_token_account(ingot_account, &smelting_state.ingot_mint, None)?;
+        let pending_smelt = Self::load_pending_smelt(
+            user_account,
+            pending_smelt_account,
+            ore_escrow,
+            coal_escrow,
+            &smelting_state,
+            program_id,
+        )?;
+
+        if PendingSmelt::commitment_for(&secret, user_account.key) != pending_smelt.commitment {
+            return Err(SmeltingError::InvalidReveal.into());
+        }
+
+        let clock = Clock::get()?;
+        if clock.slot <= pending_smelt.commit_slot {
+            return Err(SmeltingError::RevealTooEarly.into());
+        }
+        if pending_smelt.is_expired(clock.slot) {
+            return Err(SmeltingError::CommitExpired.into());
+        }
+
+        // Check if smelting succeeds, mixing the secret with the
+        // hash of the commit slot, which nobody knew when the commit was signed
+        let slot_hash = Self::slot_hash_at(slot_hashes_account, pending_smelt.commit_slot)?;
+        let seed = hashv(&[&secret, &slot_hash]).to_bytes();
+        let success = smelting_state.smelt_succeeds(&seed);
+
+        let coal_amount = pending_smelt.coal_amount;
+        let ore_amount = pending_smelt.ore_amount;
+        let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, &[smelting_state.authority_bump]];
+
+        // Burn escrowed COAL tokens
+        invoke_signed(
+            &spl_token_2022::instruction::burn_checked(
+                coal_token_program.key,
+                coal_escrow.key,
+                &smelting_state.coal_mint,
+                &forge_authority_key,
+                &[],
+                coal_amount,
+                smelting_state.coal_decimals,
+            )?,
+            &[
+                coal_escrow.clone(),
+                coal_mint.clone(),
+                forge_authority.clone(),
+                coal_token_program.clone(),
+            ],
+            &[authority_seeds],
+        )?;
+
+        let mut smelted = None;
+        if success {
+            let (ingot_amount, ore_used) = smelting_state.ore_to_ingot(ore_amount)?;
+            if ingot_amount == 0 {
+                return Err(SmeltingError::AmountTooSmall.into());
+            }
+
+            // Move the escrowed ORE backing the INGOT into the vault
+            let ore_locked = Self::transfer_tokens(
+                ore_token_program,
+                ore_escrow,
+                ore_mint,
+                ore_vault,
+                forge_authority,
+                ore_used,
+                smelting_state.ore_decimals,
+                &[authority_seeds],
+            )?;
+
+            // A transfer fee may have shaved the deposit, so only what arrived backs INGOT
+            let (ingot_amount, _) = smelting_state.ore_to_ingot(ore_locked)?;
+            if ingot_amount == 0 {
+                return Err(SmeltingError::AmountTooSmall.into());
+            }
+            if ingot_amount < min_ingot_out {
+                return Err(SmeltingError::Slippage.into());
+            }
+
+            // Check if minting more INGOT tokens would exceed the maximum supply
+            if !smelting_state.can_mint_ingot(ingot_amount)? {
+                return Err(SmeltingError::MaxSupplyExceeded.into());
+            }
+
+            // Mint INGOT tokens to user
+            invoke_signed(
+                &spl_token_2022::instruction::mint_to_checked(
+                    ingot_token_program.key,
+                    &smelting_state.ingot_mint,
+                    ingot_account.key,
+                    &forge_authority_key,
+                    &[],
+                    ingot_amount,
+                    smelting_state.ingot_decimals,
+                )?,
+                &[
+                    ingot_mint.clone(),
+                    ingot_account.clone(),
+                    forge_authority.clone(),
+                    ingot_token_program.clone(),
+                ],
+                &[authority_seeds],
+            )?;
+
+            // Refund the ORE dust that doesn't convert at the wrap ratio
+            let ore_dust = math::sub(ore_amount, ore_used)?;
+            if ore_dust > 0 {
+                Self::transfer_tokens(
+                    ore_token_program,
+                    ore_escrow,
+                    ore_mint,
+                    ore_account,
+                    forge_authority,
+                    ore_dust,
+                    smelting_state.ore_decimals,
+                    &[authority_seeds],
+                )?;
+            }
+
+            smelting_state.update_on_successful_smelt(ore_locked, ingot_amount)?;
+            smelted = Some((ore_locked, ingot_amount));
+
+            msg!(
+                "Successfully smelted {} ORE into {} INGOT",
+                ore_locked,
+                ingot_amount
+            );
+            SmeltingEvent::SmeltSucceeded(SmeltSucceeded {
+                user: *user_account.key,
+                ore_amount: ore_locked,
+                ingot_amount,
+                coal_burned: coal_amount,
+            })
+            .emit();
+        } else {
+            // Refund escrowed ORE tokens
+            Self::transfer_tokens(
+                ore_token_program,
+                ore_escrow,
+                ore_mint,
+                ore_account,
+                forge_authority,
+                ore_amount,
+                smelting_state.ore_decimals,
+                &[authority_seeds],
+            )?;
+
+            msg!(
+                "Smelting failed. {} COAL burned but no INGOT produced",
+                amount_to_ui_amount_string_trimmed(coal_amount, smelting_state.coal_decimals)
+            );
+            SmeltingEvent::SmeltFailed(SmeltFailed {
+                user: *user_account.key,
+                ore_refunded: ore_amount,
+                coal_burned: coal_amount,
+            })
+            .emit();
+        }
+
+        Self::close_pending_smelt(
+            user_account,
+            pending_smelt_account,
+            [
+                (ore_escrow, ore_mint, ore_token_program),
+                (coal_escrow, coal_mint, coal_token_program),
+            ],
+            forge_authority,
+            authority_seeds,
+        )?;
+
+        Self::update_user_stats(account_info_iter, user_account, program_id, |user_stats| {
+            match smelted {
+                Some((ore_used, ingot_amount)) => {
+                    user_stats.record_smelt(ore_used, ingot_amount, coal_amount)
+                }
+                None => user_stats.record_failed_smelt(coal_amount),
+            }
+        })?;
+
+        Ok(())
+    }
+
+    fn process_reclaim_smelt(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_account = next_account_info(account_info_iter)?;
+        let ore_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let pending_smelt_account = next_account_info(account_info_iter)?;
+        let ore_escrow = next_account_info(account_info_iter)?;
+        let coal_escrow = next_account_info(account_info_iter)?;
+        let coal_mint = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let coal_token_program = next_account_info(account_info_iter)?;
+
+        validate_signer(user_account)?;
+        let smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(ore_token_program, Some(&smelting_state.ore_token_program))?;
+        validate_token_program(coal_token_program, Some(&smelting_state.coal_token_program))?;
+        let forge_authority_key =
+            validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_mint(
+            coal_mint,
+            &smelting_state.coal_mint,
+            smelting_state.coal_decimals,
+        )?;
+        validate_token_account(ore_account, &smelting_state.ore_mint, None)?;
+        let pending_smelt = Self::load_pending_smelt(
+            user_account,
+            pending_smelt_account,
+            ore_escrow,
+            coal_escrow,
+            &smelting_state,
+            program_id,
+        )?;
+
+        let clock = Clock::get()?;
+        if !pending_smelt.is_expired(clock.slot) {
+            return Err(SmeltingError::CommitNotExpired.into());
+        }
+
+        let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, &[smelting_state.authority_bump]];
+
+        // COAL is forfeited, so withholding a losing reveal is never better than revealing it
+        invoke_signed(
+            &spl_token_2022::instruction::burn_checked(
+                coal_token_program.key,
+                coal_escrow.key,
+                &smelting_state.coal_mint,
+                &forge_authority_key,
+                &[],
+                pending_smelt.coal_amount,
+                smelting_state.coal_decimals,
+            )?,
+            &[
+                coal_escrow.clone(),
+                coal_mint.clone(),
+                forge_authority.clone(),
+                coal_token_program.clone(),
+            ],
+            &[authority_seeds],
+        )?;
+
+        // Refund escrowed ORE tokens
+        Self::transfer_tokens(
+            ore_token_program,
+            ore_escrow,
+            ore_mint,
+            ore_account,
+            forge_authority,
+            pending_smelt.ore_amount,
+            smelting_state.ore_decimals,
+            &[authority_seeds],
+        )?;
+
+        Self::close_pending_smelt(
+            user_account,
+            pending_smelt_account,
+            [
+                (ore_escrow, ore_mint, ore_token_program),
+                (coal_escrow, coal_mint, coal_token_program),
+            ],
+            forge_authority,
+            authority_seeds,
+        )?;
+
+        msg!(
+            "Reclaimed {} ORE from an expired smelt, {} COAL forfeited",
+            pending_smelt.ore_amount,
+            amount_to_ui_amount_string_trimmed(
+                pending_smelt.coal_amount,
+                smelting_state.coal_decimals
+            )
+        );
+
+        Ok(())
+    }
+
+    fn process_unsmelt(
+        accounts: &[AccountInfo],
+        amount: u64,
+        min_ore_out: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_account = next_account_info(account_info_iter)?;
+        let ore_account = next_account_info(account_info_iter)?;
+        let ingot_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let ore_vault = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+        let ore_token_program = next_account_info(account_info_iter)?;
+        let ingot_token_program = next_account_info(account_info_iter)?;
+
+        validate_signer(user_account)?;
+        let mut smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(
+            ingot_token_program,
+            Some(&smelting_state.ingot_token_program),
+        )?;
+        validate_token_program(ore_token_program, Some(&smelting_state.ore_token_program))?;
+        validate_not_paused(&smelting_state, PAUSE_UNSMELT)?;
+        validate_amount(amount, &smelting_state)?;
+        validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_mint(
+            ingot_mint,
+            &smelting_state.ingot_mint,
+            smelting_state.ingot_decimals,
+        )?;
+        validate_vault(ore_vault, &smelting_state)?;
+        validate_token_account(ore_account, &smelting_state.ore_mint, None)?;
+
+        // Check if user has enough INGOT tokens
+        let ingot_account_data = validate_token_account(
+            ingot_account,
+            &smelting_state.ingot_mint,
+            Some(user_account.key),
+        )?;
+        if ingot_account_data.amount < amount {
+            return Err(SmeltingError::InsufficientBalance.into());
+        }
+
+        let ore_amount = smelting_state.ingot_to_ore(amount)?;
+        if ore_amount == 0 {
+            return Err(SmeltingError::AmountTooSmall.into());
+        }
+        let fee = smelting_state.calculate_unsmelt_fee(ore_amount)?;
+        let ore_to_return = math::sub(ore_amount, fee)?;
+
+        // Burn INGOT tokens
+        let burn_instruction = spl_token_2022::instruction::burn_checked(
+            ingot_token_program.key,
+            ingot_account.key,
+            &smelting_state.ingot_mint,
+            user_account.key,
+            &[],
+            amount,
+            smelting_state.ingot_decimals,
+        )?;
+        invoke(
+            &burn_instruction,
+            &[
+                ingot_account.clone(),
+                ingot_mint.clone(),
+                user_account.clone(),
+                ingot_token_program.clone(),
+            ],
+        )?;
+
+        // Transfer ORE tokens from vault to user, a transfer fee counts against the minimum
+        let ore_received = Self::transfer_tokens(
+            ore_token_program,
+            ore_vault,
+            ore_mint,
+            ore_account,
+            forge_authority,
+            ore_to_return,
+            smelting_state.ore_decimals,
+            &[&[AUTHORITY_SEED, &[smelting_state.authority_bump]]],
+        )?;
+        if ore_received < min_ore_out {
+            return Err(SmeltingError::Slippage.into());
+        }
+
+        smelting_state.update_on_unsmelt(amount, ore_amount, fee)?;
+
+        msg!(
+            "Successfully unsmelted {} INGOT into {} ORE with a fee of {} ORE",
+            amount,
+            ore_received,
+            fee
+        );
+        SmeltingEvent::Unsmelted(Unsmelted {
+            user: *user_account.key,
+            ingot_amount: amount,
+            ore_returned: ore_received,
+            fee,
+        })
+        .emit();
+
+        Self::update_user_stats(account_info_iter, user_account, program_id, |user_stats| {
+            user_stats.record_unsmelt(amount, ore_amount)
+        })?;
+
+        Ok(())
+    }
+
+    fn process_transfer_ore(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let source_account = next_account_info(account_info_iter)?;
+        let destination_account = next_account_info(account_info_iter)?;
+        let authority = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let ore_mint = next_account_info(account_info_iter)?;
+
+        validate_signer(authority)?;
+        let smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(token_program, Some(&smelting_state.ore_token_program))?;
+        validate_amount(amount, &smelting_state)?;
+        validate_mint(
+            ore_mint,
+            &smelting_state.ore_mint,
+            smelting_state.ore_decimals,
+        )?;
+        validate_token_account(destination_account, &smelting_state.ore_mint, None)?;
+
+        // Check if source account has enough ORE tokens
+        let source_account_data = validate_token_account(
+            source_account,
+            &smelting_state.ore_mint,
+            Some(authority.key),
+        )?;
+        if source_account_data.amount < amount {
+            return Err(SmeltingError::InsufficientBalance.into());
+        }
+
+        Self::transfer_tokens(
+            token_program,
+            source_account,
+            ore_mint,
+            destination_account,
+            authority,
+            amount,
+            smelting_state.ore_decimals,
+            &[],
+        )?;
+
+        msg!("Successfully transferred {} ORE", amount);
+
+        Ok(())
+    }
+
+    fn process_transfer_ingot(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let source_account = next_account_info(account_info_iter)?;
+        let destination_account = next_account_info(account_info_iter)?;
+        let authority = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+
+        validate_signer(authority)?;
+        let smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_token_program(token_program, Some(&smelting_state.ingot_token_program))?;
+        validate_amount(amount, &smelting_state)?;
+        validate_mint(
+            ingot_mint,
+            &smelting_state.ingot_mint,
+            smelting_state.ingot_decimals,
+        )?;
+        validate_token_account(destination_account, &smelting_state.ingot_mint, None)?;
+
+        // Check if source account has enough INGOT tokens
+        let source_account_data = validate_token_account(
+            source_account,
+            &smelting_state.ingot_mint,
+            Some(authority.key),
+        )?;
+        if source_account_data.amount < amount {
+            return Err(SmeltingError::InsufficientBalance.into());
+        }
+
+        Self::transfer_tokens(
+            token_program,
+            source_account,
+            ingot_mint,
+            destination_account,
+            authority,
+            amount,
+            smelting_state.ingot_decimals,
+            &[],
+        )?;
+
+        msg!("Successfully transferred {} INGOT", amount);
+
+        Ok(())
+    }
+
+    fn process_close_user_stats(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_account = next_account_info(account_info_iter)?;
+        let user_stats_account = next_account_info(account_info_iter)?;
+
+        validate_signer(user_account)?;
+        let (user_stats_key, _) = find_user_stats_address(program_id, user_account.key);
+        validate_pda(user_stats_account, &user_stats_key)?;
+        if user_stats_account.owner != program_id {
+            return Err(SmeltingError::InvalidPda.into());
+        }
+        UserStats::unpack(&user_stats_account.data.borrow())?;
+
+        // Return the stats rent to the user
+        Self::close_account(user_stats_account, user_account)?;
+
+        msg!("Closed smelting stats for {}", user_account.key);
+
+        Ok(())
+    }
+
+    fn process_set_ingot_metadata(
+        accounts: &[AccountInfo],
+        name: String,
+        symbol: String,
+        uri: String,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let ingot_mint = next_account_info(account_info_iter)?;
+        let forge_authority = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        let smelting_state = validate_smelting_state(smelting_state_account, program_id)?;
+        validate_authority(authority_account, &smelting_state)?;
+        validate_mint(
+            ingot_mint,
+            &smelting_state.ingot_mint,
+            smelting_state.ingot_decimals,
+        )?;
+        validate_token_program(token_program, Some(&smelting_state.ingot_token_program))?;
+        validate_system_program(system_program_account)?;
+        let forge_authority_key =
+            validate_forge_authority(forge_authority, &smelting_state, program_id)?;
+
+        let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, &[smelting_state.authority_bump]];
+        let fields = [
+            (Field::Name, name.clone()),
+            (Field::Symbol, symbol.clone()),
+            (Field::Uri, uri.clone()),
+        ];
+
+        if *token_program.key == spl_token_2022::id() {
+            // Token-2022 reallocs the mint once per field but leaves the rent to the caller,
+            // so fund the largest size the mint reaches along the way
+            let (is_initialized, account_len) = {
+                let mint_data = ingot_mint.data.borrow();
+                let mint = StateWithExtensions::<Mint>::unpack(&mint_data)?;
+                let existing = mint.get_variable_len_extension::<TokenMetadata>().ok();
+                let mut metadata = existing.clone().unwrap_or_default();
+                let mut account_len = mint_data.len();
+                for (field, value) in fields.iter().cloned() {
+                    metadata.update(field, value);
+                    account_len = account_len
+                        .max(mint.try_get_new_account_len_for_variable_len_extension(&metadata)?);
+                }
+                (existing.is_some(), account_len)
+            };
+            let rent_shortfall = Rent::get()?
+                .minimum_balance(account_len)
+                .saturating_sub(ingot_mint.lamports());
+            if rent_shortfall > 0 {
+                invoke(
+                    &system_instruction::transfer(
+                        authority_account.key,
+                        ingot_mint.key,
+                        rent_shortfall,
+                    ),
+                    &[
+                        authority_account.clone(),
+                        ingot_mint.clone(),
+                        system_program_account.clone(),
+                    ],
+                )?;
+            }
+
+            let metadata_accounts = [
+                ingot_mint.clone(),
+                forge_authority.clone(),
+                token_program.clone(),
+            ];
+            if is_initialized {
+                for (field, value) in fields {
+                    invoke_signed(
+                        &token_metadata::update_field(
+                            token_program.key,
+                            ingot_mint.key,
+                            &forge_authority_key,
+                            field,
+                            value,
+                        ),
+                        &metadata_accounts,
+                        &[authority_seeds],
+                    )?;
+                }
+            } else {
+                invoke_signed(
+                    &token_metadata::initialize(
+                        token_program.key,
+                        ingot_mint.key,
+                        &forge_authority_key,
+                        ingot_mint.key,
+                        &forge_authority_key,
+                        name.clone(),
+                        symbol.clone(),
+                        uri.clone(),
+                    ),
+                    &metadata_accounts,
+                    &[authority_seeds],
+                )?;
+            }
+        } else {
+            let metadata_account = next_account_info(account_info_iter)?;
+            let metaplex_program = next_account_info(account_info_iter)?;
+
+            validate_pda(
+                metadata_account,
+                &metaplex::find_metadata_address(ingot_mint.key).0,
+            )?;
+            if *metaplex_program.key != metaplex::ID {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            let instruction = if metadata_account.data_is_empty() {
+                metaplex::create_metadata_account_v3(
+                    metadata_account.key,
+                    ingot_mint.key,
+                    &forge_authority_key,
+                    authority_account.key,
+                    &forge_authority_key,
+                    &name,
+                    &symbol,
+                    &uri,
+                )
+            } else {
+                metaplex::update_metadata_account_v2(
+                    metadata_account.key,
+                    &forge_authority_key,
+                    &name,
+                    &symbol,
+                    &uri,
+                )
+            };
+            invoke_signed(
+                &instruction,
+                &[
+                    metadata_account.clone(),
+                    ingot_mint.clone(),
+                    forge_authority.clone(),
+                    authority_account.clone(),
+                    system_program_account.clone(),
+                    metaplex_program.clone(),
+                ],
+                &[authority_seeds],
+            )?;
+        }
+
+        msg!("INGOT metadata set to {} ({}) at {}", name, symbol, uri);
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: *authority_account.key,
+            change: ConfigChange::IngotMetadata { name, symbol, uri },
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn process_migrate_state(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let smelting_state_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        validate_system_program(system_program_account)?;
+        validate_pda(
+            smelting_state_account,
+            &find_smelting_state_address(program_id).0,
+        )?;
+        if smelting_state_account.owner != program_id {
+            return Err(SmeltingError::InvalidStateAccount.into());
+        }
+        let (smelting_state, from_version) = {
+            let data = smelting_state_account.data.borrow();
+            (
+                SmeltingState::unpack(&data)?,
+                SmeltingState::layout_version(&data)?,
+            )
+        };
+        validate_authority(authority_account, &smelting_state)?;
+        if from_version == STATE_VERSION {
+            msg!(
+                "Smelting state is already at layout version {}",
+                STATE_VERSION
+            );
+            return Ok(());
+        }
+
+        // Fund the rent for the new size before growing the account
+        let rent_shortfall = Rent::get()?
+            .minimum_balance(SmeltingState::LEN)
+            .saturating_sub(smelting_state_account.lamports());
+        if rent_shortfall > 0 {
+            invoke(
+                &system_instruction::transfer(
+                    authority_account.key,
+                    smelting_state_account.key,
+                    rent_shortfall,
+                ),
+                &[
+                    authority_account.clone(),
+                    smelting_state_account.clone(),
+                    system_program_account.clone(),
+                ],
+            )?;
+        }
+        smelting_state_account.realloc(SmeltingState::LEN, true)?;
+
+        SmeltingState::pack(
+            smelting_state,
+            &mut smelting_state_account.data.borrow_mut(),
+        )?;
+
+        msg!(
+            "Smelting state migrated from layout version {} to {}",
+            from_version,
+            STATE_VERSION
+        );
+        SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority: *authority_account.key,
+            change: ConfigChange::StateMigrated { from_version },
+        })
+        .emit();
+
+        Ok(())
+    }
+
+    fn create_pda_account<'a>(
+        payer: &AccountInfo<'a>,
+        new_account: &AccountInfo<'a>,
+        space: usize,
+        owner: &Pubkey,
+        system_program_account: &AccountInfo<'a>,
+        signer_seeds: &[&[u8]],
+        rent: &Rent,
+    ) -> ProgramResult {
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                new_account.key,
+                rent.minimum_balance(space),
+                space as u64,
+                owner,
+            ),
+            &[
+                payer.clone(),
+                new_account.clone(),
+                system_program_account.clone(),
+            ],
+            &[signer_seeds],
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_token_account<'a>(
+        payer: &AccountInfo<'a>,
+        token_account: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        owner: &Pubkey,
+        token_program: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        signer_seeds: &[&[u8]],
+        rent: &Rent,
+    ) -> ProgramResult {
+        // Token-2022 mints can require extensions on every account, e.g. to hold withheld fees
+        let account_len = {
+            let mint_data = mint.data.borrow();
+            let mint_extensions =
+                StateWithExtensions::<Mint>::unpack(&mint_data)?.get_extension_types()?;
+            ExtensionType::try_calculate_account_len::<TokenAccount>(
+                &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+            )?
+        };
+        Self::create_pda_account(
+            payer,
+            token_account,
+            account_len,
+            token_program.key,
+            system_program_account,
+            signer_seeds,
+            rent,
+        )?;
+        invoke(
+            &spl_token_2022::instruction::initialize_account3(
+                token_program.key,
+                token_account.key,
+                mint.key,
+                owner,
+            )?,
+            &[token_account.clone(), mint.clone(), token_program.clone()],
+        )
+    }
+
+    // Works for both token programs. Returns what `destination` actually received,
+    // which is less than `amount` for mints with a transfer fee
+    #[allow(clippy::too_many_arguments)]
+    fn transfer_tokens<'a>(
+        token_program: &AccountInfo<'a>,
+        source: &AccountInfo<'a>,
+        mint: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        authority: &AccountInfo<'a>,
+        amount: u64,
+        decimals: u8,
+        signer_seeds: &[&[&[u8]]],
+    ) -> Result<u64, ProgramError> {
+        let balance_before = Self::token_balance(destination)?;
+        invoke_signed(
+            &spl_token_2022::instruction::transfer_checked(
+                token_program.key,
+                source.key,
+                mint.key,
+                destination.key,
+                authority.key,
+                &[],
+                amount,
+                decimals,
+            )?,
+            &[
+                source.clone(),
+                mint.clone(),
+                destination.clone(),
+                authority.clone(),
+                token_program.clone(),
+            ],
+            signer_seeds,
+        )?;
+        math::sub(Self::token_balance(destination)?, balance_before)
+    }
+
+    fn token_balance(token_account: &AccountInfo) -> Result<u64, ProgramError> {
+        Ok(
+            StateWithExtensions::<TokenAccount>::unpack(&token_account.data.borrow())?
+                .base
+                .amount,
+        )
+    }
+
+    fn load_pending_smelt(
+        user_account: &AccountInfo,
+        pending_smelt_account: &AccountInfo,
+        ore_escrow: &AccountInfo,
+        coal_escrow: &AccountInfo,
+        smelting_state: &SmeltingState,
+        program_id: &Pubkey,
+    ) -> Result<PendingSmelt, ProgramError> {
+        if pending_smelt_account.owner != program_id {
+            return Err(SmeltingError::InvalidPda.into());
+        }
+        let pending_smelt = PendingSmelt::unpack(&pending_smelt_account.data.borrow())?;
+        if pending_smelt.user != *user_account.key {
+            return Err(SmeltingError::InvalidPda.into());
+        }
+
+        let pending_smelt_key = Pubkey::create_program_address(
+            &[
+                PENDING_SMELT_SEED,
+                user_account.key.as_ref(),
+                &[pending_smelt.bump],
+            ],
+            program_id,
+        )?;
+        let ore_escrow_key = Pubkey::create_program_address(
+            &[
+                ESCROW_SEED,
+                pending_smelt_key.as_ref(),
+                smelting_state.ore_mint.as_ref(),
+                &[pending_smelt.ore_escrow_bump],
+            ],
+            program_id,
+        )?;
+        let coal_escrow_key = Pubkey::create_program_address(
+            &[
+                ESCROW_SEED,
+                pending_smelt_key.as_ref(),
+                smelting_state.coal_mint.as_ref(),
+                &[pending_smelt.coal_escrow_bump],
+            ],
+            program_id,
+        )?;
+        validate_pda(pending_smelt_account, &pending_smelt_key)?;
+        validate_pda(ore_escrow, &ore_escrow_key)?;
+        validate_pda(coal_escrow, &coal_escrow_key)?;
+
+        Ok(pending_smelt)
+    }
+
+    // Each escrow comes with its mint and token program
+    fn close_pending_smelt<'a>(
+        user_account: &AccountInfo<'a>,
+        pending_smelt_account: &AccountInfo<'a>,
+        escrows: [(&AccountInfo<'a>, &AccountInfo<'a>, &AccountInfo<'a>); 2],
+        forge_authority: &AccountInfo<'a>,
+        authority_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        for (escrow, mint, token_program) in escrows {
+            // Fees withheld by a transfer-fee mint would block closing the escrow
+            let withheld_fees = {
+                let escrow_data = escrow.data.borrow();
+                StateWithExtensions::<TokenAccount>::unpack(&escrow_data)?
+                    .get_extension::<TransferFeeAmount>()
+                    .map_or(0, |fee_amount| u64::from(fee_amount.withheld_amount))
+            };
+            if withheld_fees > 0 {
+                invoke(
+                    &harvest_withheld_tokens_to_mint(token_program.key, mint.key, &[escrow.key])?,
+                    &[mint.clone(), escrow.clone(), token_program.clone()],
+                )?;
+            }
+
+            invoke_signed(
+                &spl_token_2022::instruction::close_account(
+                    token_program.key,
+                    escrow.key,
+                    user_account.key,
+                    forge_authority.key,
+                    &[],
+                )?,
+                &[
+                    escrow.clone(),
+                    user_account.clone(),
+                    forge_authority.clone(),
+                    token_program.clone(),
+                ],
+                &[authority_seeds],
+            )?;
+        }
+
+        // Return the pending smelt rent to the user
+        Self::close_account(pending_smelt_account, user_account)
+    }
+
+    // Hands a program-owned account's lamports to `destination` and gives the emptied
+    // account back to the system program, so it can't be revived within the transaction
+    fn close_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+        let lamports = account.lamports();
+        **account.lamports.borrow_mut() = 0;
+        **destination.lamports.borrow_mut() = math::add(destination.lamports(), lamports)?;
+        account.realloc(0, false)?;
+        account.assign(&system_program::id());
+        Ok(())
+    }
+
+    // Smelt, reveal and unsmelt take an optional trailing user stats PDA, followed
+    // by the system program so it can be created on first use
+    fn update_user_stats<'a>(
+        account_info_iter: &mut Iter<AccountInfo<'a>>,
+        user_account: &AccountInfo<'a>,
+        program_id: &Pubkey,
+        record: impl FnOnce(&mut UserStats) -> ProgramResult,
+    ) -> ProgramResult {
+        let Ok(user_stats_account) = next_account_info(account_info_iter) else {
+            return Ok(());
+        };
+
+        let mut user_stats = if user_stats_account.data_is_empty() {
+            let system_program_account = next_account_info(account_info_iter)?;
+            validate_system_program(system_program_account)?;
+            let (user_stats_key, bump) = find_user_stats_address(program_id, user_account.key);
+            validate_pda(user_stats_account, &user_stats_key)?;
+
+            Self::create_pda_account(
+                user_account,
+                user_stats_account,
+                UserStats::LEN,
+                program_id,
+                system_program_account,
+                &[USER_STATS_SEED, user_account.key.as_ref(), &[bump]],
+                &Rent::get()?,
+            )?;
+            UserStats {
+                is_initialized: true,
+                user: *user_account.key,
+                bump,
+                ..UserStats::default()
+            }
+        } else {
+            if user_stats_account.owner != program_id {
+                return Err(SmeltingError::InvalidPda.into());
+            }
+            let user_stats = UserStats::unpack(&user_stats_account.data.borrow())?;
+            if user_stats.user != *user_account.key {
+                return Err(SmeltingError::InvalidPda.into());
+            }
+            user_stats
+        };
+
+        record(&mut user_stats)?;
+
+        UserStats::pack(user_stats, &mut user_stats_account.data.borrow_mut())
+    }
+
+    fn slot_hash_at(
+        slot_hashes_account: &AccountInfo,
+        slot: u64,
+    ) -> Result<[u8; 32], ProgramError> {
+        // SlotHashes is too large to deserialize on-chain, so binary search the raw
+        // entries: a u64 length followed by (slot, hash) pairs, newest first
+        const ENTRY_LEN: usize = 8 + 32;
+        let data = slot_hashes_account.data.borrow();
+        if data.len() < 8 {
+            return Err(SmeltingError::InvalidSysvar.into());
+        }
+        let len = u64::from_le_bytes(*array_ref![data, 0, 8]) as usize;
+        let (mut low, mut high) = (0, len.min((data.len() - 8) / ENTRY_LEN));
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let entry = array_ref![data, 8 + mid * ENTRY_LEN, ENTRY_LEN];
+            let (entry_slot, entry_hash) = array_refs![entry, 8, 32];
+            match u64::from_le_bytes(*entry_slot).cmp(&slot) {
+                Ordering::Equal => return Ok(*entry_hash),
+                Ordering::Greater => low = mid + 1,
+                Ordering::Less => high = mid,
+            }
+        }
+
+        Err(SmeltingError::SlotHashNotFound.into())
+    }
 }
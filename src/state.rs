@@ -1,40 +1,197 @@
+use crate::{
+    constants::{
+        AUTHORITY_SEED, BACKPOINTER_SEED, ESCROW_SEED, MAX_AMOUNT, MAX_INGOT_SUPPLY,
+        MIN_DIFFICULTY, ORE_VAULT_SEED, PENDING_SMELT_SEED, SMELTING_SUCCESS_RATE,
+        SMELT_COMMIT_TIMEOUT_SLOTS, UNSMELT_FEE_PERCENTAGE, USER_STATS_SEED, WRAPPED_MINT_SEED,
+    },
+    error::SmeltingError,
+    math,
+};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use bytemuck::{Pod, Zeroable};
 use solana_program::{
+    entrypoint::ProgramResult,
+    hash::{hashv, Hash},
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
-use crate::constants::MAX_INGOT_SUPPLY;
+use std::mem::{align_of, size_of};
+
+pub fn find_smelting_state_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[BACKPOINTER_SEED], program_id)
+}
+
+pub fn find_forge_authority_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[AUTHORITY_SEED], program_id)
+}
 
-pub const UNSMELT_FEE_PERCENTAGE: u8 = 5;
+pub fn find_ingot_mint_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[WRAPPED_MINT_SEED], program_id)
+}
+
+pub fn find_ore_vault_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ORE_VAULT_SEED], program_id)
+}
+
+pub fn find_pending_smelt_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[PENDING_SMELT_SEED, user.as_ref()], program_id)
+}
 
+pub fn find_escrow_address(
+    program_id: &Pubkey,
+    pending_smelt: &Pubkey,
+    mint: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ESCROW_SEED, pending_smelt.as_ref(), mint.as_ref()],
+        program_id,
+    )
+}
+
+pub fn find_user_stats_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[USER_STATS_SEED, user.as_ref()], program_id)
+}
+
+/// Forge configuration and accounting. Stored after an 8-byte header and read in
+/// place, so fields are ordered to leave no padding.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Pod, Zeroable)]
+#[cfg_attr(feature = "idl", derive(borsh::BorshSchema))]
 pub struct SmeltingState {
-    pub is_initialized: bool,
+    pub total_ingots_minted: u64,
+    pub total_ore_locked: u64,
+    pub total_fees_accrued: u64,
+    pub wrap_ratio_numerator: u64,
+    pub wrap_ratio_denominator: u64,
+    pub coal_per_ore: u64,
+    pub coal_base_cost: u64,
+    pub max_ingot_supply: u64,
+    pub max_amount: u64,
     pub authority: Pubkey,
-    pub authority_bump: u8,
+    pub pending_authority: Pubkey,
+    pub treasury: Pubkey,
     pub ore_mint: Pubkey,
     pub ingot_mint: Pubkey,
     pub coal_mint: Pubkey,
     pub ore_vault: Pubkey,
-    pub total_ingots_minted: u64,
-    pub total_ore_locked: u64,
+    pub ore_token_program: Pubkey,
+    pub coal_token_program: Pubkey,
+    pub ingot_token_program: Pubkey,
+    pub challenge: [u8; 32],
+    pub unsmelt_fee_bps: u16,
+    pub is_initialized: u8,
+    pub authority_bump: u8,
     pub ore_decimals: u8,
     pub ingot_decimals: u8,
     pub coal_decimals: u8,
+    pub difficulty: u8,
+    pub success_rate: u8,
+    pub paused_operations: u8,
+    // Zeroed room for new fields without another realloc
+    pub reserved: [u8; 126],
+}
+
+// Version byte, padded so the state after it stays 8-byte aligned
+pub const STATE_HEADER_LEN: usize = 8;
+
+const _: () = assert!(size_of::<SmeltingState>() == 560);
+const _: () = assert!(align_of::<SmeltingState>() <= STATE_HEADER_LEN);
+
+impl Default for SmeltingState {
+    fn default() -> Self {
+        Self::zeroed()
+    }
 }
 
 impl Sealed for SmeltingState {}
 
 impl IsInitialized for SmeltingState {
     fn is_initialized(&self) -> bool {
-        self.is_initialized
+        self.is_initialized != 0
     }
 }
 
+// Bumped whenever fields move into the reserved space
+pub const STATE_VERSION: u8 = 1;
+
+// The unversioned layout the program was first deployed with: flag, authority and
+// bump, mints and vault, totals and decimals
+const LEGACY_STATE_LEN: usize = 181;
+
 impl Pack for SmeltingState {
-    const LEN: usize = 1 + 32 + 1 + 32 + 32 + 32 + 32 + 8 + 8 + 1 + 1 + 1;
+    const LEN: usize = STATE_HEADER_LEN + size_of::<SmeltingState>();
+
+    // Accepts every historical layout, not only accounts of `LEN` bytes
+    fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let state = Self::unpack_unchecked(input)?;
+        if !state.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(state)
+    }
+
+    fn unpack_unchecked(input: &[u8]) -> Result<Self, ProgramError> {
+        Self::unpack_from_slice(input)
+    }
 
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-        let src = array_ref![src, 0, SmeltingState::LEN];
+        match Self::layout_version(src)? {
+            STATE_VERSION => Ok(bytemuck::pod_read_unaligned(&src[STATE_HEADER_LEN..])),
+            _ => Self::unpack_unversioned(array_ref![src, 0, LEGACY_STATE_LEN]),
+        }
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let (header, state) = dst[..SmeltingState::LEN].split_at_mut(STATE_HEADER_LEN);
+        header.fill(0);
+        header[0] = STATE_VERSION;
+        state.copy_from_slice(bytemuck::bytes_of(self));
+    }
+}
+
+impl SmeltingState {
+    /// Layout version of a stored state account, 0 for the unversioned layouts.
+    pub fn layout_version(data: &[u8]) -> Result<u8, ProgramError> {
+        match data.len() {
+            SmeltingState::LEN if data[0] == STATE_VERSION => Ok(STATE_VERSION),
+            LEGACY_STATE_LEN => Ok(0),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Borrows the state of a current-layout account in place.
+    pub fn load(data: &[u8]) -> Result<&Self, ProgramError> {
+        Self::check_current(data)?;
+        let state: &Self = bytemuck::try_from_bytes(&data[STATE_HEADER_LEN..])
+            .map_err(|_| ProgramError::InvalidAccountData)?;
+        if !state.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(state)
+    }
+
+    /// Mutably borrows the state of a current-layout account in place.
+    pub fn load_mut(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        Self::check_current(data)?;
+        let state: &mut Self = bytemuck::try_from_bytes_mut(&mut data[STATE_HEADER_LEN..])
+            .map_err(|_| ProgramError::InvalidAccountData)?;
+        if !state.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(state)
+    }
+
+    // Older layouts still unpack, but must go through MigrateState before they can be written
+    fn check_current(data: &[u8]) -> ProgramResult {
+        if Self::layout_version(data)? != STATE_VERSION {
+            return Err(SmeltingError::StateNotMigrated.into());
+        }
+        Ok(())
+    }
+
+    // Missing fields get what the forge behaved as before they existed
+    fn unpack_unversioned(src: &[u8; LEGACY_STATE_LEN]) -> Result<Self, ProgramError> {
         let (
             is_initialized,
             authority,
@@ -51,7 +208,7 @@ impl Pack for SmeltingState {
         ) = array_refs![src, 1, 32, 1, 32, 32, 32, 32, 8, 8, 1, 1, 1];
 
         Ok(SmeltingState {
-            is_initialized: is_initialized[0] != 0,
+            is_initialized: is_initialized[0],
             authority: Pubkey::new_from_array(*authority),
             authority_bump: authority_bump[0],
             ore_mint: Pubkey::new_from_array(*ore_mint),
@@ -63,95 +220,335 @@ impl Pack for SmeltingState {
             ore_decimals: ore_decimals[0],
             ingot_decimals: ingot_decimals[0],
             coal_decimals: coal_decimals[0],
+            difficulty: MIN_DIFFICULTY,
+            wrap_ratio_numerator: 1,
+            wrap_ratio_denominator: 1,
+            // Every ORE base unit burned one COAL base unit
+            coal_per_ore: math::to_u64(math::pow10(ore_decimals[0])?)?,
+            success_rate: SMELTING_SUCCESS_RATE,
+            unsmelt_fee_bps: UNSMELT_FEE_PERCENTAGE as u16 * 100,
+            max_ingot_supply: MAX_INGOT_SUPPLY,
+            max_amount: MAX_AMOUNT,
+            ore_token_program: spl_token::id(),
+            coal_token_program: spl_token::id(),
+            ingot_token_program: spl_token::id(),
+            ..Self::zeroed()
+        })
+    }
+}
+
+impl SmeltingState {
+    pub fn forge_authority(&self, program_id: &Pubkey) -> Result<Pubkey, ProgramError> {
+        Pubkey::create_program_address(&[AUTHORITY_SEED, &[self.authority_bump]], program_id)
+            .map_err(|_| ProgramError::InvalidSeeds)
+    }
+
+    pub fn proof_hash(&self, user: &Pubkey, nonce: u64) -> Hash {
+        hashv(&[user.as_ref(), &self.challenge, &nonce.to_le_bytes()])
+    }
+
+    pub fn meets_difficulty(proof: &Hash, difficulty: u8) -> bool {
+        let mut leading_zeros = 0u32;
+        for byte in proof.to_bytes() {
+            leading_zeros += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        leading_zeros >= difficulty as u32
+    }
+
+    pub fn rotate_challenge(&mut self, proof: &Hash) {
+        self.challenge = hashv(&[&self.challenge, proof.as_ref()]).to_bytes();
+    }
+
+    pub fn calculate_unsmelt_fee(&self, amount: u64) -> Result<u64, ProgramError> {
+        math::to_u64(math::mul(amount as u128, self.unsmelt_fee_bps as u128)? / 10_000)
+    }
+
+    pub fn can_mint_ingot(&self, amount: u64) -> Result<bool, ProgramError> {
+        Ok(math::add(self.total_ingots_minted, amount)? <= self.max_ingot_supply)
+    }
+
+    pub fn is_paused(&self, operation: u8) -> bool {
+        self.paused_operations & operation != 0
+    }
+
+    pub fn smelt_succeeds(&self, seed: &[u8; 32]) -> bool {
+        (seed[0] as u16) < ((self.success_rate as u16 * 256) / 100)
+    }
+
+    // ORE in, INGOT out: INGOT is rounded down and only the ORE needed to back it
+    // (rounded up) is taken, so any dust never leaves the user's wallet.
+    pub fn ore_to_ingot(&self, ore_amount: u64) -> Result<(u64, u64), ProgramError> {
+        let (numerator, denominator) = self.scaled_wrap_ratio()?;
+        let ingot_amount = math::mul(ore_amount as u128, numerator)? / denominator;
+        let ore_used = math::mul(ingot_amount, denominator)?.div_ceil(numerator);
+        Ok((math::to_u64(ingot_amount)?, math::to_u64(ore_used)?))
+    }
+
+    // INGOT in, ORE out: ORE is rounded down, leaving the remainder in the vault.
+    pub fn ingot_to_ore(&self, ingot_amount: u64) -> Result<u64, ProgramError> {
+        let (numerator, denominator) = self.scaled_wrap_ratio()?;
+        math::to_u64(math::mul(ingot_amount as u128, denominator)? / numerator)
+    }
+
+    // Wrap ratio in base units: INGOT per ORE, scaled by each mint's decimals
+    fn scaled_wrap_ratio(&self) -> Result<(u128, u128), ProgramError> {
+        if self.wrap_ratio_numerator == 0 || self.wrap_ratio_denominator == 0 {
+            return Err(SmeltingError::InvalidWrapRatio.into());
+        }
+        let numerator = math::mul(
+            self.wrap_ratio_numerator as u128,
+            math::pow10(self.ingot_decimals)?,
+        )?;
+        let denominator = math::mul(
+            self.wrap_ratio_denominator as u128,
+            math::pow10(self.ore_decimals)?,
+        )?;
+        Ok((numerator, denominator))
+    }
+
+    // COAL burned for smelting `ore_amount`: a flat base cost plus `coal_per_ore`
+    // COAL base units for every whole ORE, rounded up.
+    pub fn coal_cost(&self, ore_amount: u64) -> Result<u64, ProgramError> {
+        let variable_cost = math::mul(ore_amount as u128, self.coal_per_ore as u128)?
+            .div_ceil(math::pow10(self.ore_decimals)?);
+        math::add(math::to_u64(variable_cost)?, self.coal_base_cost)
+    }
+
+    pub fn update_on_successful_smelt(
+        &mut self,
+        ore_amount: u64,
+        ingot_amount: u64,
+    ) -> ProgramResult {
+        let total_ingots_minted = math::add(self.total_ingots_minted, ingot_amount)?;
+        if total_ingots_minted > self.max_ingot_supply {
+            return Err(SmeltingError::MaxSupplyExceeded.into());
+        }
+        self.total_ore_locked = math::add(self.total_ore_locked, ore_amount)?;
+        self.total_ingots_minted = total_ingots_minted;
+        Ok(())
+    }
+
+    // The fee stays in the vault but moves from backing ORE to protocol revenue
+    pub fn update_on_unsmelt(
+        &mut self,
+        ingot_amount: u64,
+        ore_amount: u64,
+        fee: u64,
+    ) -> ProgramResult {
+        self.total_ingots_minted = math::sub(self.total_ingots_minted, ingot_amount)?;
+        self.total_ore_locked = math::sub(self.total_ore_locked, ore_amount)?;
+        self.total_fees_accrued = math::add(self.total_fees_accrued, fee)?;
+        Ok(())
+    }
+
+    pub fn update_on_fee_withdrawal(&mut self, amount: u64) -> ProgramResult {
+        self.total_fees_accrued = self
+            .total_fees_accrued
+            .checked_sub(amount)
+            .ok_or(SmeltingError::InsufficientFees)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSmelt {
+    pub is_initialized: bool,
+    pub user: Pubkey,
+    pub ore_amount: u64,
+    pub coal_amount: u64,
+    pub commitment: [u8; 32],
+    pub commit_slot: u64,
+    pub bump: u8,
+    pub ore_escrow_bump: u8,
+    pub coal_escrow_bump: u8,
+}
+
+impl Sealed for PendingSmelt {}
+
+impl IsInitialized for PendingSmelt {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for PendingSmelt {
+    const LEN: usize = 1 + 32 + 8 + 8 + 32 + 8 + 1 + 1 + 1;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = src
+            .get(..PendingSmelt::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let src = array_ref![src, 0, PendingSmelt::LEN];
+        let (
+            is_initialized,
+            user,
+            ore_amount,
+            coal_amount,
+            commitment,
+            commit_slot,
+            bump,
+            ore_escrow_bump,
+            coal_escrow_bump,
+        ) = array_refs![src, 1, 32, 8, 8, 32, 8, 1, 1, 1];
+
+        Ok(PendingSmelt {
+            is_initialized: is_initialized[0] != 0,
+            user: Pubkey::new_from_array(*user),
+            ore_amount: u64::from_le_bytes(*ore_amount),
+            coal_amount: u64::from_le_bytes(*coal_amount),
+            commitment: *commitment,
+            commit_slot: u64::from_le_bytes(*commit_slot),
+            bump: bump[0],
+            ore_escrow_bump: ore_escrow_bump[0],
+            coal_escrow_bump: coal_escrow_bump[0],
         })
     }
 
     fn pack_into_slice(&self, dst: &mut [u8]) {
-        let dst = array_mut_ref![dst, 0, SmeltingState::LEN];
+        let dst = array_mut_ref![dst, 0, PendingSmelt::LEN];
         let (
             is_initialized_dst,
-            authority_dst,
-            authority_bump_dst,
-            ore_mint_dst,
-            ingot_mint_dst,
-            coal_mint_dst,
-            ore_vault_dst,
-            total_ingots_minted_dst,
-            total_ore_locked_dst,
-            ore_decimals_dst,
-            ingot_decimals_dst,
-            coal_decimals_dst,
-        ) = mut_array_refs![dst, 1, 32, 1, 32, 32, 32, 32, 8, 8, 1, 1, 1];
+            user_dst,
+            ore_amount_dst,
+            coal_amount_dst,
+            commitment_dst,
+            commit_slot_dst,
+            bump_dst,
+            ore_escrow_bump_dst,
+            coal_escrow_bump_dst,
+        ) = mut_array_refs![dst, 1, 32, 8, 8, 32, 8, 1, 1, 1];
 
         is_initialized_dst[0] = self.is_initialized as u8;
-        authority_dst.copy_from_slice(self.authority.as_ref());
-        authority_bump_dst[0] = self.authority_bump;
-        ore_mint_dst.copy_from_slice(self.ore_mint.as_ref());
-        ingot_mint_dst.copy_from_slice(self.ingot_mint.as_ref());
-        coal_mint_dst.copy_from_slice(self.coal_mint.as_ref());
-        ore_vault_dst.copy_from_slice(self.ore_vault.as_ref());
-        *total_ingots_minted_dst = self.total_ingots_minted.to_le_bytes();
-        *total_ore_locked_dst = self.total_ore_locked.to_le_bytes();
-        ore_decimals_dst[0] = self.ore_decimals;
-        ingot_decimals_dst[0] = self.ingot_decimals;
-        coal_decimals_dst[0] = self.coal_decimals;
+        user_dst.copy_from_slice(self.user.as_ref());
+        *ore_amount_dst = self.ore_amount.to_le_bytes();
+        *coal_amount_dst = self.coal_amount.to_le_bytes();
+        *commitment_dst = self.commitment;
+        *commit_slot_dst = self.commit_slot.to_le_bytes();
+        bump_dst[0] = self.bump;
+        ore_escrow_bump_dst[0] = self.ore_escrow_bump;
+        coal_escrow_bump_dst[0] = self.coal_escrow_bump;
     }
 }
 
-impl Default for SmeltingState {
-    fn default() -> Self {
-        SmeltingState {
-            is_initialized: false,
-            authority: Pubkey::default(),
-            authority_bump: 0,
-            ore_mint: Pubkey::default(),
-            ingot_mint: Pubkey::default(),
-            coal_mint: Pubkey::default(),
-            ore_vault: Pubkey::default(),
-            total_ingots_minted: 0,
-            total_ore_locked: 0,
-            ore_decimals: 0,
-            ingot_decimals: 0,
-            coal_decimals: 0,
-        }
+impl PendingSmelt {
+    pub fn commitment_for(secret: &[u8; 32], user: &Pubkey) -> [u8; 32] {
+        hashv(&[secret, user.as_ref()]).to_bytes()
+    }
+
+    pub fn is_expired(&self, slot: u64) -> bool {
+        slot > self.commit_slot.saturating_add(SMELT_COMMIT_TIMEOUT_SLOTS)
     }
 }
 
-impl SmeltingState {
-    pub fn calculate_unsmelt_fee(amount: u64) -> u64 {
-        amount.saturating_mul(UNSMELT_FEE_PERCENTAGE as u64) / 100
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserStats {
+    pub is_initialized: bool,
+    pub user: Pubkey,
+    pub bump: u8,
+    pub smelt_attempts: u64,
+    pub successful_smelts: u64,
+    pub coal_burned: u64,
+    pub ore_locked: u64,
+    pub ingots_minted: u64,
+    pub ingots_unsmelted: u64,
+}
+
+impl Sealed for UserStats {}
+
+impl IsInitialized for UserStats {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
     }
+}
 
-    pub fn can_mint_ingot(&self, amount: u64) -> bool {
-        self.total_ingots_minted.saturating_add(amount) <= MAX_INGOT_SUPPLY
+impl Pack for UserStats {
+    const LEN: usize = 1 + 32 + 1 + 8 + 8 + 8 + 8 + 8 + 8;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let src = src
+            .get(..UserStats::LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let src = array_ref![src, 0, UserStats::LEN];
+        let (
+            is_initialized,
+            user,
+            bump,
+            smelt_attempts,
+            successful_smelts,
+            coal_burned,
+            ore_locked,
+            ingots_minted,
+            ingots_unsmelted,
+        ) = array_refs![src, 1, 32, 1, 8, 8, 8, 8, 8, 8];
+
+        Ok(UserStats {
+            is_initialized: is_initialized[0] != 0,
+            user: Pubkey::new_from_array(*user),
+            bump: bump[0],
+            smelt_attempts: u64::from_le_bytes(*smelt_attempts),
+            successful_smelts: u64::from_le_bytes(*successful_smelts),
+            coal_burned: u64::from_le_bytes(*coal_burned),
+            ore_locked: u64::from_le_bytes(*ore_locked),
+            ingots_minted: u64::from_le_bytes(*ingots_minted),
+            ingots_unsmelted: u64::from_le_bytes(*ingots_unsmelted),
+        })
     }
 
-    pub fn update_on_successful_smelt(&mut self, amount: u64) -> ProgramResult {
-        let ingot_amount = self.convert_amount(amount, self.ore_decimals, self.ingot_decimals);
-        self.total_ingots_minted = self.total_ingots_minted.saturating_add(ingot_amount);
-        self.total_ore_locked = self.total_ore_locked.saturating_add(amount);
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let dst = array_mut_ref![dst, 0, UserStats::LEN];
+        let (
+            is_initialized_dst,
+            user_dst,
+            bump_dst,
+            smelt_attempts_dst,
+            successful_smelts_dst,
+            coal_burned_dst,
+            ore_locked_dst,
+            ingots_minted_dst,
+            ingots_unsmelted_dst,
+        ) = mut_array_refs![dst, 1, 32, 1, 8, 8, 8, 8, 8, 8];
+
+        is_initialized_dst[0] = self.is_initialized as u8;
+        user_dst.copy_from_slice(self.user.as_ref());
+        bump_dst[0] = self.bump;
+        *smelt_attempts_dst = self.smelt_attempts.to_le_bytes();
+        *successful_smelts_dst = self.successful_smelts.to_le_bytes();
+        *coal_burned_dst = self.coal_burned.to_le_bytes();
+        *ore_locked_dst = self.ore_locked.to_le_bytes();
+        *ingots_minted_dst = self.ingots_minted.to_le_bytes();
+        *ingots_unsmelted_dst = self.ingots_unsmelted.to_le_bytes();
+    }
+}
 
-        if self.total_ingots_minted > MAX_INGOT_SUPPLY {
-            return Err(ProgramError::AccountDataTooSmall);
-        }
+impl UserStats {
+    pub fn record_smelt(
+        &mut self,
+        ore_amount: u64,
+        ingot_amount: u64,
+        coal_amount: u64,
+    ) -> ProgramResult {
+        self.smelt_attempts = math::add(self.smelt_attempts, 1)?;
+        self.successful_smelts = math::add(self.successful_smelts, 1)?;
+        self.coal_burned = math::add(self.coal_burned, coal_amount)?;
+        self.ore_locked = math::add(self.ore_locked, ore_amount)?;
+        self.ingots_minted = math::add(self.ingots_minted, ingot_amount)?;
         Ok(())
     }
 
-    fn convert_amount(&self, amount: u64, from_decimals: u8, to_decimals: u8) -> u64 {
-        if from_decimals == to_decimals {
-            amount
-        } else if from_decimals > to_decimals {
-            amount / 10u64.pow((from_decimals - to_decimals) as u32)
-        } else {
-            amount * 10u64.pow((to_decimals - from_decimals) as u32)
-        }
+    pub fn record_failed_smelt(&mut self, coal_amount: u64) -> ProgramResult {
+        self.smelt_attempts = math::add(self.smelt_attempts, 1)?;
+        self.coal_burned = math::add(self.coal_burned, coal_amount)?;
+        Ok(())
     }
 
-    pub fn update_on_unsmelt(&mut self, amount: u64, fee: u64) {
-        self.total_ingots_minted = self.total_ingots_minted.saturating_sub(amount);
-        self.total_ore_locked = self
-            .total_ore_locked
-            .saturating_sub(amount)
-            .saturating_add(fee);
+    // Stats may be opened after the user already smelted, or the INGOT may have come
+    // from someone else, so only the ORE these stats saw locked is released
+    pub fn record_unsmelt(&mut self, ingot_amount: u64, ore_amount: u64) -> ProgramResult {
+        self.ore_locked = math::sub(self.ore_locked, ore_amount.min(self.ore_locked))?;
+        self.ingots_unsmelted = math::add(self.ingots_unsmelted, ingot_amount)?;
+        Ok(())
     }
 }
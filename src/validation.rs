@@ -0,0 +1,179 @@
+use std::cell::RefMut;
+
+use arrayref::array_ref;
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+    sysvar::slot_hashes,
+};
+use spl_token_2022::{
+    extension::StateWithExtensions,
+    state::{Account as TokenAccount, Mint},
+};
+
+use crate::{error::SmeltingError, state::SmeltingState};
+
+pub fn validate_signer(account: &AccountInfo) -> ProgramResult {
+    if !account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+fn is_token_program(key: &Pubkey) -> bool {
+    *key == spl_token::id() || *key == spl_token_2022::id()
+}
+
+// Either token program, or exactly the one the forge recorded for a mint
+pub fn validate_token_program(account: &AccountInfo, expected: Option<&Pubkey>) -> ProgramResult {
+    if !is_token_program(account.key) || expected.is_some_and(|expected| account.key != expected) {
+        return Err(SmeltingError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+pub fn validate_system_program(account: &AccountInfo) -> ProgramResult {
+    if *account.key != system_program::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+pub fn validate_slot_hashes(account: &AccountInfo) -> ProgramResult {
+    if !slot_hashes::check_id(account.key) {
+        return Err(SmeltingError::InvalidSysvar.into());
+    }
+    Ok(())
+}
+
+pub fn validate_pda(account: &AccountInfo, expected: &Pubkey) -> ProgramResult {
+    if account.key != expected {
+        return Err(SmeltingError::InvalidPda.into());
+    }
+    Ok(())
+}
+
+// ProgramData tag, then the deploy slot and an optional upgrade authority
+const PROGRAM_DATA_TAG: u32 = 3;
+
+// Only whoever can upgrade the program gets to set up its forge
+pub fn validate_upgrade_authority(
+    program_data: &AccountInfo,
+    authority: &AccountInfo,
+    program_id: &Pubkey,
+) -> ProgramResult {
+    validate_pda(
+        program_data,
+        &bpf_loader_upgradeable::get_program_data_address(program_id),
+    )?;
+    if !bpf_loader_upgradeable::check_id(program_data.owner) {
+        return Err(SmeltingError::InvalidPda.into());
+    }
+    let data = program_data.try_borrow_data()?;
+    if data.len() < UpgradeableLoaderState::size_of_programdata_metadata()
+        || u32::from_le_bytes(*array_ref![data, 0, 4]) != PROGRAM_DATA_TAG
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // A frozen program has no upgrade authority, so nobody can initialize it
+    if data[12] != 1 || authority.key.as_ref() != &data[13..45] {
+        return Err(SmeltingError::InvalidAuthority.into());
+    }
+    Ok(())
+}
+
+// Borrowed in place, writes land in the account without repacking
+pub fn validate_smelting_state<'a>(
+    account: &'a AccountInfo,
+    program_id: &Pubkey,
+) -> Result<RefMut<'a, SmeltingState>, ProgramError> {
+    if account.owner != program_id {
+        return Err(SmeltingError::InvalidStateAccount.into());
+    }
+    SmeltingState::load(&account.try_borrow_data()?)?;
+    RefMut::filter_map(account.try_borrow_mut_data()?, |data| {
+        SmeltingState::load_mut(data).ok()
+    })
+    .map_err(|_| SmeltingError::InvalidStateAccount.into())
+}
+
+// The forge admin stored in the state, not the authority PDA
+pub fn validate_authority(account: &AccountInfo, smelting_state: &SmeltingState) -> ProgramResult {
+    validate_signer(account)?;
+    if *account.key != smelting_state.authority {
+        return Err(SmeltingError::InvalidAuthority.into());
+    }
+    Ok(())
+}
+
+pub fn validate_forge_authority(
+    account: &AccountInfo,
+    smelting_state: &SmeltingState,
+    program_id: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    let forge_authority_key = smelting_state.forge_authority(program_id)?;
+    validate_pda(account, &forge_authority_key)?;
+    Ok(forge_authority_key)
+}
+
+pub fn validate_amount(amount: u64, smelting_state: &SmeltingState) -> ProgramResult {
+    if amount > smelting_state.max_amount {
+        return Err(SmeltingError::InvalidAmount.into());
+    }
+    Ok(())
+}
+
+pub fn validate_not_paused(smelting_state: &SmeltingState, operation: u8) -> ProgramResult {
+    if smelting_state.is_paused(operation) {
+        return Err(SmeltingError::Paused.into());
+    }
+    Ok(())
+}
+
+// The configured mint, still at the decimals the forge recorded for it
+pub fn validate_mint(account: &AccountInfo, expected: &Pubkey, decimals: u8) -> ProgramResult {
+    if account.key != expected {
+        return Err(SmeltingError::MintMismatch.into());
+    }
+    if validate_mint_account(account)?.decimals != decimals {
+        return Err(SmeltingError::DecimalsMismatch.into());
+    }
+    Ok(())
+}
+
+pub fn validate_mint_account(account: &AccountInfo) -> Result<Mint, ProgramError> {
+    if !is_token_program(account.owner) {
+        return Err(SmeltingError::InvalidTokenProgram.into());
+    }
+    Ok(StateWithExtensions::<Mint>::unpack(&account.data.borrow())?.base)
+}
+
+pub fn validate_vault(account: &AccountInfo, smelting_state: &SmeltingState) -> ProgramResult {
+    if *account.key != smelting_state.ore_vault {
+        return Err(SmeltingError::InvalidVault.into());
+    }
+    Ok(())
+}
+
+// A token account of `mint`, optionally held by `owner`
+pub fn validate_token_account(
+    account: &AccountInfo,
+    mint: &Pubkey,
+    owner: Option<&Pubkey>,
+) -> Result<TokenAccount, ProgramError> {
+    if !is_token_program(account.owner) {
+        return Err(SmeltingError::InvalidTokenProgram.into());
+    }
+    let token_account = StateWithExtensions::<TokenAccount>::unpack(&account.data.borrow())?.base;
+    if token_account.mint != *mint {
+        return Err(SmeltingError::MintMismatch.into());
+    }
+    if owner.is_some_and(|owner| token_account.owner != *owner) {
+        return Err(SmeltingError::InvalidTokenAccountOwner.into());
+    }
+    Ok(token_account)
+}
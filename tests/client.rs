@@ -0,0 +1,189 @@
+use solana_program::{instruction::InstructionError, program_pack::Pack, pubkey::Pubkey};
+use theforgeonsolana::{
+    client::{decode_instruction_error, AccountData, ClientError, Forge, MockRpc},
+    error::SmeltingError,
+    state::{PendingSmelt, SmeltingState, UserStats},
+};
+
+fn forge_with_state() -> (Forge, MockRpc, SmeltingState) {
+    let forge = Forge::new(Pubkey::new_unique());
+    let state = SmeltingState {
+        is_initialized: 1,
+        ore_mint: Pubkey::new_unique(),
+        coal_mint: Pubkey::new_unique(),
+        ingot_mint: forge.ingot_mint,
+        ore_vault: forge.ore_vault,
+        difficulty: 8,
+        wrap_ratio_numerator: 1,
+        wrap_ratio_denominator: 1,
+        ore_token_program: spl_token::id(),
+        coal_token_program: spl_token_2022::id(),
+        ingot_token_program: spl_token::id(),
+        ..SmeltingState::default()
+    };
+    let rpc = MockRpc::new();
+    rpc.set_packed(&forge.program_id, forge.state, state);
+    (forge, rpc, state)
+}
+
+#[test]
+fn fetches_and_decodes_state() {
+    let (forge, rpc, state) = forge_with_state();
+    assert_eq!(forge.fetch_state(&rpc).unwrap(), state);
+}
+
+#[test]
+fn missing_state_is_an_error() {
+    let (forge, rpc, _) = forge_with_state();
+    rpc.remove_account(&forge.state);
+    assert!(matches!(
+        forge.fetch_state(&rpc),
+        Err(ClientError::AccountNotFound(address)) if address == forge.state
+    ));
+}
+
+#[test]
+fn rejects_accounts_owned_by_another_program() {
+    let (forge, rpc, _) = forge_with_state();
+    let owner = Pubkey::new_unique();
+    rpc.set_account(
+        forge.state,
+        AccountData {
+            owner,
+            lamports: 1,
+            data: vec![0; SmeltingState::LEN],
+        },
+    );
+    assert!(matches!(
+        forge.fetch_state(&rpc),
+        Err(ClientError::InvalidOwner { owner: actual, .. }) if actual == owner
+    ));
+}
+
+#[test]
+fn optional_user_accounts() {
+    let (forge, rpc, _) = forge_with_state();
+    let user = Pubkey::new_unique();
+    assert_eq!(forge.fetch_pending_smelt(&rpc, &user).unwrap(), None);
+    assert_eq!(forge.fetch_user_stats(&rpc, &user).unwrap(), None);
+
+    let stats = UserStats {
+        is_initialized: true,
+        user,
+        smelt_attempts: 3,
+        successful_smelts: 2,
+        ..UserStats::default()
+    };
+    rpc.set_packed(
+        &forge.program_id,
+        forge.user_stats_address(&user),
+        stats.clone(),
+    );
+    assert_eq!(forge.fetch_user_stats(&rpc, &user).unwrap(), Some(stats));
+
+    let pending = PendingSmelt {
+        is_initialized: true,
+        user,
+        ore_amount: 10,
+        coal_amount: 10,
+        commitment: [7; 32],
+        commit_slot: 42,
+        bump: 0,
+        ore_escrow_bump: 0,
+        coal_escrow_bump: 0,
+    };
+    rpc.set_packed(
+        &forge.program_id,
+        forge.pending_smelt_address(&user),
+        pending.clone(),
+    );
+    assert_eq!(
+        forge.fetch_pending_smelt(&rpc, &user).unwrap(),
+        Some(pending)
+    );
+}
+
+#[test]
+fn builds_a_minable_smelt() {
+    let (forge, rpc, _) = forge_with_state();
+    let state = forge.fetch_state(&rpc).unwrap();
+    let user = Pubkey::new_unique();
+    let nonce = Forge::find_nonce(&state, &user, 0, 1 << 20).unwrap();
+    assert!(SmeltingState::meets_difficulty(
+        &state.proof_hash(&user, nonce),
+        state.difficulty
+    ));
+
+    let (ore, coal, ingot) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let instruction = forge.smelt(&state, &user, &ore, &coal, &ingot, 100, nonce, 0);
+    let accounts = instruction
+        .accounts
+        .iter()
+        .map(|meta| meta.pubkey)
+        .collect::<Vec<_>>();
+    assert_eq!(instruction.program_id, forge.program_id);
+    assert_eq!(
+        accounts,
+        [
+            user,
+            ore,
+            coal,
+            ingot,
+            forge.state,
+            forge.forge_authority,
+            forge.ore_vault,
+            state.ore_mint,
+            state.coal_mint,
+            forge.ingot_mint,
+            spl_token::id(),
+            spl_token_2022::id(),
+            spl_token::id(),
+        ]
+    );
+
+    let instruction = forge.with_user_stats(instruction, &user);
+    assert!(instruction.accounts[0].is_writable);
+    assert_eq!(
+        instruction.accounts[13].pubkey,
+        forge.user_stats_address(&user)
+    );
+}
+
+#[test]
+fn decodes_forge_error_codes() {
+    let decoded = decode_instruction_error(&InstructionError::Custom(6000));
+    assert_eq!(decoded, Some(SmeltingError::MaxSupplyExceeded));
+    assert_eq!(
+        decoded.unwrap().to_string(),
+        "Max supply of INGOT tokens exceeded"
+    );
+    assert_eq!(
+        decode_instruction_error(&InstructionError::Custom(
+            SmeltingError::StateNotMigrated as u32
+        )),
+        Some(SmeltingError::StateNotMigrated)
+    );
+    assert_eq!(
+        decode_instruction_error(&InstructionError::Custom(u32::MAX)),
+        None
+    );
+    // SPL Token's InsufficientFunds, raised through a CPI
+    assert_eq!(decode_instruction_error(&InstructionError::Custom(1)), None);
+    // The codes the first three errors had before they were renumbered
+    for (old_code, error) in [
+        (0, SmeltingError::MaxSupplyExceeded),
+        (1, SmeltingError::InsufficientBalance),
+        (2, SmeltingError::InvalidInstruction),
+    ] {
+        assert_eq!(SmeltingError::from_code(old_code), None);
+        assert_eq!(SmeltingError::from_code(old_code + 6000), Some(error));
+    }
+    assert_eq!(
+        decode_instruction_error(&InstructionError::MissingRequiredSignature),
+        None
+    );
+}
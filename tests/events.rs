@@ -0,0 +1,50 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::pubkey::Pubkey;
+use theforgeonsolana::events::{SmeltSucceeded, SmeltingEvent, Unsmelted, EVENT_TAG};
+
+fn data_line(event: &SmeltingEvent) -> String {
+    format!(
+        "Program data: {} {}",
+        STANDARD.encode(EVENT_TAG),
+        STANDARD.encode(borsh::to_vec(event).unwrap())
+    )
+}
+
+#[test]
+fn only_events_logged_by_the_forge_decode() {
+    let forge = Pubkey::new_unique();
+    let other = Pubkey::new_unique();
+    let smelted = SmeltingEvent::SmeltSucceeded(SmeltSucceeded {
+        user: Pubkey::new_unique(),
+        ore_amount: 2,
+        ingot_amount: 2,
+        coal_burned: 1,
+    });
+    let forged = SmeltingEvent::Unsmelted(Unsmelted {
+        user: Pubkey::new_unique(),
+        ingot_amount: 1_000,
+        ore_returned: 1_000,
+        fee: 0,
+    });
+
+    let logs = [
+        format!("Program {other} invoke [1]"),
+        data_line(&forged),
+        format!("Program {other} success"),
+        format!("Program {forge} invoke [1]"),
+        "Program log: Instruction: Smelt".to_owned(),
+        // A CPI'd program logs while the forge is still running
+        format!("Program {other} invoke [2]"),
+        data_line(&forged),
+        format!("Program {other} consumed 100 of 200000 compute units"),
+        format!("Program {other} success"),
+        data_line(&smelted),
+        format!("Program {forge} success"),
+        format!("Program {other} invoke [1]"),
+        data_line(&forged),
+        format!("Program {other} failed: custom program error: 0x0"),
+    ];
+
+    let events = SmeltingEvent::decode_logs(&forge, logs.iter().map(String::as_str));
+    assert_eq!(events, [smelted]);
+}
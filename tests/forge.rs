@@ -0,0 +1,1712 @@
+use solana_program::{
+    bpf_loader_upgradeable,
+    program_pack::{IsInitialized, Pack},
+    pubkey::Pubkey,
+    system_instruction,
+};
+#[cfg(feature = "test-sbf")]
+use solana_program::{instruction::AccountMeta, system_program};
+use solana_program_test::{
+    processor, BanksClient, BanksClientError, ProgramTest, ProgramTestBanksClientExt,
+    ProgramTestContext,
+};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    instruction::{Instruction, InstructionError},
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    state::{Account as TokenAccount, Mint},
+};
+use spl_token_metadata_interface::state::TokenMetadata;
+#[cfg(feature = "test-sbf")]
+use theforgeonsolana::events::{
+    ConfigChange, ConfigChanged, SmeltSucceeded, SmeltingEvent, Unsmelted,
+};
+#[cfg(feature = "test-sbf")]
+use theforgeonsolana::state::find_forge_authority_address;
+use theforgeonsolana::{
+    constants::{
+        MAX_DIFFICULTY, MAX_UNSMELT_FEE_BPS, MIN_DIFFICULTY, PAUSE_ALL, PAUSE_SMELT, PAUSE_UNSMELT,
+        SMELT_COMMIT_TIMEOUT_SLOTS,
+    },
+    error::SmeltingError,
+    instruction::{self, ConfigParam, ForgeMints, SmeltingInstruction},
+    processor::Processor,
+    state::{
+        find_ingot_mint_address, find_ore_vault_address, find_pending_smelt_address,
+        find_smelting_state_address, find_user_stats_address, PendingSmelt, SmeltingState,
+        UserStats, STATE_VERSION,
+    },
+};
+
+const DECIMALS: u8 = 6;
+const PROGRAM_DATA_LAMPORTS: u64 = 1_000_000_000;
+const ONE: u64 = 1_000_000;
+// Ceiling for one PoW smelt under SPL Token, lower it as the program gets cheaper
+#[cfg(feature = "test-sbf")]
+const SMELT_COMPUTE_UNITS: u64 = 50_000;
+// The forge before SmeltingState was read in place, built by scripts/build-pack-baseline.sh
+#[cfg(feature = "test-sbf")]
+const PACK_BASELINE_PROGRAM: &str = "theforgeonsolana_pack";
+
+struct Forge {
+    context: ProgramTestContext,
+    program_id: Pubkey,
+    mint_authority: Keypair,
+    ore_mint: Pubkey,
+    coal_mint: Pubkey,
+    ingot_mint: Pubkey,
+}
+
+struct User {
+    keypair: Keypair,
+    ore: Pubkey,
+    coal: Pubkey,
+    ingot: Pubkey,
+}
+
+async fn try_process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let blockhash = context
+        .banks_client
+        .get_new_latest_blockhash(&context.last_blockhash)
+        .await
+        .unwrap();
+    context.last_blockhash = blockhash;
+
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+// Forge events decoded from the transaction's log messages
+#[cfg(feature = "test-sbf")]
+async fn process_events(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Vec<SmeltingEvent> {
+    let blockhash = context
+        .banks_client
+        .get_new_latest_blockhash(&context.last_blockhash)
+        .await
+        .unwrap();
+    context.last_blockhash = blockhash;
+
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    let outcome = context
+        .banks_client
+        .process_transaction_with_metadata(transaction)
+        .await
+        .unwrap();
+    outcome.result.unwrap();
+    let logs = outcome.metadata.unwrap().log_messages;
+    let program_id = instructions[0].program_id;
+    SmeltingEvent::decode_logs(&program_id, logs.iter().map(String::as_str))
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) {
+    try_process(context, instructions, signers).await.unwrap();
+}
+
+async fn assert_fails(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+    expected: SmeltingError,
+) {
+    let error = try_process(context, instructions, signers)
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        error,
+        TransactionError::InstructionError(0, InstructionError::Custom(expected as u32))
+    );
+}
+
+async fn create_mint(context: &mut ProgramTestContext, authority: &Pubkey) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint2(
+            &spl_token::id(),
+            &mint.pubkey(),
+            authority,
+            None,
+            DECIMALS,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await;
+    mint.pubkey()
+}
+
+// A Token-2022 mint that withholds `fee_bps` of every transfer
+async fn create_transfer_fee_mint(
+    context: &mut ProgramTestContext,
+    authority: &Pubkey,
+    fee_bps: u16,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let len = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+        .unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(len),
+            len as u64,
+            &spl_token_2022::id(),
+        ),
+        spl_token_2022::extension::transfer_fee::instruction::initialize_transfer_fee_config(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            None,
+            None,
+            fee_bps,
+            u64::MAX,
+        )
+        .unwrap(),
+        spl_token_2022::instruction::initialize_mint2(
+            &spl_token_2022::id(),
+            &mint.pubkey(),
+            authority,
+            None,
+            DECIMALS,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&mint]).await;
+    mint.pubkey()
+}
+
+// Sized for whatever extensions the mint requires on its accounts
+async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let mint_account = context
+        .banks_client
+        .get_account(*mint)
+        .await
+        .unwrap()
+        .unwrap();
+    let mint_extensions = StateWithExtensions::<Mint>::unpack(&mint_account.data)
+        .unwrap()
+        .get_extension_types()
+        .unwrap();
+    let len = ExtensionType::try_calculate_account_len::<TokenAccount>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    )
+    .unwrap();
+
+    let account = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &context.payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(len),
+            len as u64,
+            &mint_account.owner,
+        ),
+        spl_token_2022::instruction::initialize_account3(
+            &mint_account.owner,
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    process(context, &instructions, &[&account]).await;
+    account.pubkey()
+}
+
+async fn token_balance(banks_client: &mut BanksClient, account: &Pubkey) -> u64 {
+    let account = banks_client.get_account(*account).await.unwrap().unwrap();
+    StateWithExtensions::<TokenAccount>::unpack(&account.data)
+        .unwrap()
+        .base
+        .amount
+}
+
+async fn smelting_state(forge: &mut Forge) -> SmeltingState {
+    let (state, _) = find_smelting_state_address(&forge.program_id);
+    let account = forge
+        .context
+        .banks_client
+        .get_account(state)
+        .await
+        .unwrap()
+        .unwrap();
+    SmeltingState::unpack(&account.data).unwrap()
+}
+
+async fn setup() -> Forge {
+    setup_with_ore_transfer_fee(None).await
+}
+
+// With a fee, ORE is a Token-2022 mint and INGOT is created under Token-2022 too
+async fn setup_with_ore_transfer_fee(fee_bps: Option<u16>) -> Forge {
+    let program_id = Pubkey::new_unique();
+    let program_test = ProgramTest::new(
+        "theforgeonsolana",
+        program_id,
+        processor!(Processor::process),
+    );
+    start(program_test, program_id, fee_bps).await
+}
+
+// Runs the built `theforgeonsolana.so` instead, the only way compute units get metered
+// and events reach the transaction logs
+#[cfg(feature = "test-sbf")]
+async fn setup_sbf() -> Forge {
+    let program_id = Pubkey::new_unique();
+    start(sbf_program_test(program_id), program_id, None).await
+}
+
+#[cfg(feature = "test-sbf")]
+fn sbf_program_test(program_id: Pubkey) -> ProgramTest {
+    let mut program_test = ProgramTest::new("theforgeonsolana", program_id, None);
+    program_test.prefer_bpf(true);
+    program_test
+}
+
+// The ProgramData account of an upgradeable deploy, which Initialize reads the upgrade
+// authority from
+async fn set_program_data(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    upgrade_authority: Option<&Pubkey>,
+) {
+    let mut data = vec![0; 45];
+    data[..4].copy_from_slice(&3u32.to_le_bytes());
+    if let Some(upgrade_authority) = upgrade_authority {
+        data[12] = 1;
+        data[13..].copy_from_slice(upgrade_authority.as_ref());
+    }
+    let program_data = Account {
+        lamports: PROGRAM_DATA_LAMPORTS,
+        data,
+        owner: bpf_loader_upgradeable::id(),
+        ..Account::default()
+    };
+    let address = bpf_loader_upgradeable::get_program_data_address(program_id);
+    let replaced = context.banks_client.get_balance(address).await.unwrap();
+    context.set_account(&address, &AccountSharedData::from(program_data));
+
+    // Taken from the payer, or the bank's capitalization no longer adds up once a slot is warped
+    let payer = context.payer.pubkey();
+    let mut payer_account = context
+        .banks_client
+        .get_account(payer)
+        .await
+        .unwrap()
+        .unwrap();
+    payer_account.lamports -= PROGRAM_DATA_LAMPORTS - replaced;
+    context.set_account(&payer, &AccountSharedData::from(payer_account));
+}
+
+async fn start(program_test: ProgramTest, program_id: Pubkey, fee_bps: Option<u16>) -> Forge {
+    let mut context = program_test.start_with_context().await;
+
+    let mint_authority = Keypair::new();
+    let (ore_mint, token_program) = match fee_bps {
+        Some(fee_bps) => (
+            create_transfer_fee_mint(&mut context, &mint_authority.pubkey(), fee_bps).await,
+            spl_token_2022::id(),
+        ),
+        None => (
+            create_mint(&mut context, &mint_authority.pubkey()).await,
+            spl_token::id(),
+        ),
+    };
+    let coal_mint = create_mint(&mut context, &mint_authority.pubkey()).await;
+
+    let payer = context.payer.insecure_clone();
+    set_program_data(&mut context, &program_id, Some(&payer.pubkey())).await;
+    process(
+        &mut context,
+        &[instruction::initialize(
+            &program_id,
+            &payer.pubkey(),
+            &ore_mint,
+            &coal_mint,
+            &token_program,
+            &token_program,
+            DECIMALS,
+            1,
+            1,
+        )],
+        &[],
+    )
+    .await;
+    // Reveal rolls always succeed, so tests settle deterministically
+    process(
+        &mut context,
+        &[instruction::update_config(
+            &program_id,
+            &payer.pubkey(),
+            ConfigParam::SuccessRate(100),
+        )],
+        &[],
+    )
+    .await;
+
+    let (ingot_mint, _) = find_ingot_mint_address(&program_id);
+    Forge {
+        context,
+        program_id,
+        mint_authority,
+        ore_mint,
+        coal_mint,
+        ingot_mint,
+    }
+}
+
+async fn create_user(forge: &mut Forge, ore: u64, coal: u64) -> User {
+    let keypair = Keypair::new();
+    let owner = keypair.pubkey();
+    let ore_account = create_token_account(&mut forge.context, &forge.ore_mint, &owner).await;
+    let coal_account = create_token_account(&mut forge.context, &forge.coal_mint, &owner).await;
+    let ingot_account = create_token_account(&mut forge.context, &forge.ingot_mint, &owner).await;
+
+    let mints = ForgeMints::from(&smelting_state(forge).await);
+    let mint_authority = forge.mint_authority.insecure_clone();
+    let mut instructions = vec![];
+    if ore > 0 {
+        instructions.push(
+            spl_token_2022::instruction::mint_to(
+                &mints.ore_token_program,
+                &forge.ore_mint,
+                &ore_account,
+                &mint_authority.pubkey(),
+                &[],
+                ore,
+            )
+            .unwrap(),
+        );
+    }
+    if coal > 0 {
+        instructions.push(
+            spl_token_2022::instruction::mint_to(
+                &mints.coal_token_program,
+                &forge.coal_mint,
+                &coal_account,
+                &mint_authority.pubkey(),
+                &[],
+                coal,
+            )
+            .unwrap(),
+        );
+    }
+    if !instructions.is_empty() {
+        process(&mut forge.context, &instructions, &[&mint_authority]).await;
+    }
+
+    User {
+        keypair,
+        ore: ore_account,
+        coal: coal_account,
+        ingot: ingot_account,
+    }
+}
+
+// Grind a nonce for the current challenge the same way a miner would
+fn mine(state: &SmeltingState, user: &Pubkey) -> u64 {
+    (0..)
+        .find(|nonce| {
+            SmeltingState::meets_difficulty(&state.proof_hash(user, *nonce), state.difficulty)
+        })
+        .unwrap()
+}
+
+async fn smelt(forge: &mut Forge, user: &User, amount: u64) {
+    let state = smelting_state(forge).await;
+    let nonce = mine(&state, &user.keypair.pubkey());
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &ForgeMints::from(&state),
+        amount,
+        nonce,
+        0,
+    );
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+}
+
+async fn fund_key(forge: &mut Forge, key: &Pubkey) {
+    let payer = forge.context.payer.pubkey();
+    process(
+        &mut forge.context,
+        &[system_instruction::transfer(&payer, key, 1_000_000_000)],
+        &[],
+    )
+    .await;
+}
+
+// Lamports for the pending smelt and escrow rent a commit takes from the user
+async fn fund(forge: &mut Forge, user: &User) {
+    fund_key(forge, &user.keypair.pubkey()).await;
+}
+
+async fn commit(forge: &mut Forge, user: &User, amount: u64, secret: &[u8; 32]) -> PendingSmelt {
+    let mints = ForgeMints::from(&smelting_state(forge).await);
+    let owner = user.keypair.pubkey();
+    let instruction = instruction::commit_smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &mints,
+        amount,
+        PendingSmelt::commitment_for(secret, &owner),
+    );
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    let (pending_smelt, _) = find_pending_smelt_address(&forge.program_id, &owner);
+    let account = forge
+        .context
+        .banks_client
+        .get_account(pending_smelt)
+        .await
+        .unwrap()
+        .unwrap();
+    PendingSmelt::unpack(&account.data).unwrap()
+}
+
+fn reveal(forge: &Forge, mints: &ForgeMints, user: &User, secret: [u8; 32]) -> Instruction {
+    instruction::reveal_smelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.ingot,
+        mints,
+        secret,
+        0,
+    )
+}
+
+async fn pending_smelt_exists(forge: &mut Forge, user: &User) -> bool {
+    let (pending_smelt, _) = find_pending_smelt_address(&forge.program_id, &user.keypair.pubkey());
+    forge
+        .context
+        .banks_client
+        .get_account(pending_smelt)
+        .await
+        .unwrap()
+        .is_some()
+}
+
+#[tokio::test]
+async fn initialize() {
+    let mut forge = setup().await;
+    let state = smelting_state(&mut forge).await;
+
+    assert!(state.is_initialized());
+
+    assert_eq!(state.authority, forge.context.payer.pubkey());
+    assert_eq!(state.ore_mint, forge.ore_mint);
+    assert_eq!(state.coal_mint, forge.coal_mint);
+    assert_eq!(state.ingot_mint, forge.ingot_mint);
+    assert_eq!(state.ore_vault, find_ore_vault_address(&forge.program_id).0);
+    assert_eq!(state.ore_decimals, DECIMALS);
+    assert_eq!(state.ingot_decimals, DECIMALS);
+    assert_eq!(state.coal_decimals, DECIMALS);
+    assert_eq!(state.total_ingots_minted, 0);
+    assert_eq!(state.total_ore_locked, 0);
+    assert_eq!(
+        token_balance(&mut forge.context.banks_client, &state.ore_vault).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn initialize_requires_the_upgrade_authority() {
+    let mut forge = setup().await;
+    let stranger = Keypair::new();
+    let payer = forge.context.payer.pubkey();
+    fund_key(&mut forge, &stranger.pubkey()).await;
+
+    // Checked before anything else, so a second initialize shows who may call it
+    let initialize = |authority: &Pubkey| {
+        instruction::initialize(
+            &forge.program_id,
+            authority,
+            &forge.ore_mint,
+            &forge.coal_mint,
+            &spl_token::id(),
+            &spl_token::id(),
+            DECIMALS,
+            1,
+            1,
+        )
+    };
+    let (by_stranger, by_payer) = (initialize(&stranger.pubkey()), initialize(&payer));
+    assert_fails(
+        &mut forge.context,
+        &[by_stranger],
+        &[&stranger],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+
+    // A frozen program has no upgrade authority at all
+    set_program_data(&mut forge.context, &forge.program_id, None).await;
+    assert_fails(
+        &mut forge.context,
+        &[by_payer],
+        &[],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn smelt_locks_ore_burns_coal_and_mints_ingot() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+
+    // Only reveals roll, a valid proof mints whatever the success rate
+    let authority = forge.context.payer.pubkey();
+    let instruction =
+        instruction::update_config(&forge.program_id, &authority, ConfigParam::SuccessRate(1));
+    process(&mut forge.context, &[instruction], &[]).await;
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    let state = smelting_state(&mut forge).await;
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ore).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.coal).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.ingot).await, 2 * ONE);
+    assert_eq!(token_balance(banks_client, &state.ore_vault).await, 2 * ONE);
+    assert_eq!(state.total_ingots_minted, 2 * ONE);
+    assert_eq!(state.total_ore_locked, 2 * ONE);
+}
+
+#[tokio::test]
+async fn unsmelt_returns_ore_minus_fee() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    let mints = ForgeMints::from(&smelting_state(&mut forge).await);
+    let instruction = instruction::unsmelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        0,
+    );
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    let state = smelting_state(&mut forge).await;
+    let fee = state.calculate_unsmelt_fee(ONE).unwrap();
+    assert!(fee > 0);
+
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ingot).await, ONE);
+    assert_eq!(token_balance(banks_client, &user.ore).await, 9 * ONE - fee);
+    assert_eq!(
+        token_balance(banks_client, &state.ore_vault).await,
+        ONE + fee
+    );
+    assert_eq!(state.total_ingots_minted, ONE);
+    assert_eq!(state.total_ore_locked, ONE);
+    assert_eq!(state.total_fees_accrued, fee);
+}
+
+#[tokio::test]
+async fn user_stats_track_smelts_and_unsmelts_until_closed() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let other = create_user(&mut forge, 0, 0).await;
+    fund(&mut forge, &user).await;
+    fund(&mut forge, &other).await;
+    let owner = user.keypair.pubkey();
+    let (user_stats, _) = find_user_stats_address(&forge.program_id, &owner);
+
+    // The first recorded smelt opens the stats account
+    let state = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&state);
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &mints,
+        2 * ONE,
+        mine(&state, &owner),
+        0,
+    );
+    let instruction = instruction::with_user_stats(instruction, &forge.program_id, &owner);
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    let instruction = instruction::unsmelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        0,
+    );
+    let instruction = instruction::with_user_stats(instruction, &forge.program_id, &owner);
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    let banks_client = &mut forge.context.banks_client;
+    let coal_burned = 10 * ONE - token_balance(banks_client, &user.coal).await;
+    let account = banks_client.get_account(user_stats).await.unwrap().unwrap();
+    let stats = UserStats::unpack(&account.data).unwrap();
+    assert_eq!(stats.user, owner);
+    assert_eq!(stats.smelt_attempts, 1);
+    assert_eq!(stats.successful_smelts, 1);
+    assert_eq!(stats.coal_burned, coal_burned);
+    assert_eq!(stats.ingots_minted, 2 * ONE);
+    assert_eq!(stats.ingots_unsmelted, ONE);
+    assert_eq!(stats.ore_locked, ONE);
+
+    // Nobody else can close them and take the rent
+    let mut instruction = instruction::close_user_stats(&forge.program_id, &owner);
+    instruction.accounts[0].pubkey = other.keypair.pubkey();
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&other.keypair],
+        SmeltingError::InvalidPda,
+    )
+    .await;
+
+    let lamports = forge.context.banks_client.get_balance(owner).await.unwrap();
+    let instruction = instruction::close_user_stats(&forge.program_id, &owner);
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    let banks_client = &mut forge.context.banks_client;
+    assert!(banks_client
+        .get_account(user_stats)
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        banks_client.get_balance(owner).await.unwrap(),
+        lamports + account.lamports
+    );
+
+    // Handed back to the system program, so the next recorded smelt starts over
+    let state = smelting_state(&mut forge).await;
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &mints,
+        ONE,
+        mine(&state, &owner),
+        0,
+    );
+    let instruction = instruction::with_user_stats(instruction, &forge.program_id, &owner);
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+    let account = forge
+        .context
+        .banks_client
+        .get_account(user_stats)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.owner, forge.program_id);
+    let stats = UserStats::unpack(&account.data).unwrap();
+    assert_eq!(stats.smelt_attempts, 1);
+    assert_eq!(stats.ingots_minted, ONE);
+}
+
+#[tokio::test]
+async fn failures_carry_their_error_code() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, ONE, ONE).await;
+
+    let payer = forge.context.payer.insecure_clone();
+    let cap = instruction::update_config(
+        &forge.program_id,
+        &payer.pubkey(),
+        ConfigParam::MaxIngotSupply(ONE / 2),
+    );
+    process(&mut forge.context, &[cap], &[]).await;
+    let state = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&state);
+
+    let failures = [
+        (
+            instruction::smelt(
+                &forge.program_id,
+                &user.keypair.pubkey(),
+                &user.ore,
+                &user.coal,
+                &user.ingot,
+                &mints,
+                ONE,
+                mine(&state, &user.keypair.pubkey()),
+                0,
+            ),
+            vec![&user.keypair],
+            SmeltingError::MaxSupplyExceeded,
+        ),
+        (
+            instruction::unsmelt(
+                &forge.program_id,
+                &user.keypair.pubkey(),
+                &user.ore,
+                &user.ingot,
+                &mints,
+                0,
+                0,
+            ),
+            vec![&user.keypair],
+            SmeltingError::InvalidAmount,
+        ),
+        (
+            Instruction::new_with_bytes(forge.program_id, &[u8::MAX], vec![]),
+            vec![],
+            SmeltingError::InvalidInstruction,
+        ),
+    ];
+    for (instruction, signers, expected) in failures {
+        let error = try_process(&mut forge.context, &[instruction], &signers)
+            .await
+            .unwrap_err()
+            .unwrap();
+        assert_eq!(
+            error,
+            TransactionError::InstructionError(0, InstructionError::Custom(expected as u32))
+        );
+    }
+}
+
+#[tokio::test]
+async fn outputs_below_the_minimum_fail_with_slippage() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let state = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&state);
+
+    // 1:1 wrap ratio, so 2 ORE smelts into exactly 2 INGOT
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &mints,
+        2 * ONE,
+        mine(&state, &user.keypair.pubkey()),
+        2 * ONE + 1,
+    );
+    let error = try_process(&mut forge.context, &[instruction], &[&user.keypair])
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        error,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(SmeltingError::Slippage as u32)
+        )
+    );
+
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    // The unsmelt fee keeps the ORE out below the INGOT burned
+    let instruction = instruction::unsmelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        ONE,
+    );
+    let error = try_process(&mut forge.context, &[instruction], &[&user.keypair])
+        .await
+        .unwrap_err()
+        .unwrap();
+    assert_eq!(
+        error,
+        TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(SmeltingError::Slippage as u32)
+        )
+    );
+
+    let fee = smelting_state(&mut forge)
+        .await
+        .calculate_unsmelt_fee(ONE)
+        .unwrap();
+    let instruction = instruction::unsmelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        ONE - fee,
+    );
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+    assert_eq!(
+        token_balance(&mut forge.context.banks_client, &user.ore).await,
+        9 * ONE - fee
+    );
+}
+
+#[tokio::test]
+async fn config_updates_stay_within_bounds() {
+    let mut forge = setup().await;
+    let authority = forge.context.payer.pubkey();
+
+    let failures = [
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::UnsmeltFeeBps(MAX_UNSMELT_FEE_BPS + 1),
+            ),
+            SmeltingError::InvalidConfig,
+        ),
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::Difficulty(MIN_DIFFICULTY - 1),
+            ),
+            SmeltingError::InvalidDifficulty,
+        ),
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::Difficulty(MAX_DIFFICULTY + 1),
+            ),
+            SmeltingError::InvalidDifficulty,
+        ),
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::CoalCost {
+                    coal_per_ore: 0,
+                    coal_base_cost: 0,
+                },
+            ),
+            SmeltingError::InvalidConfig,
+        ),
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::CoalCost {
+                    coal_per_ore: u64::MAX,
+                    coal_base_cost: 0,
+                },
+            ),
+            SmeltingError::InvalidConfig,
+        ),
+        (
+            instruction::update_config(
+                &forge.program_id,
+                &authority,
+                ConfigParam::CoalCost {
+                    coal_per_ore: ONE,
+                    coal_base_cost: u64::MAX,
+                },
+            ),
+            SmeltingError::InvalidConfig,
+        ),
+    ];
+    for (instruction, expected) in failures {
+        assert_fails(&mut forge.context, &[instruction], &[], expected).await;
+    }
+
+    let instructions = [
+        instruction::update_config(
+            &forge.program_id,
+            &authority,
+            ConfigParam::UnsmeltFeeBps(MAX_UNSMELT_FEE_BPS),
+        ),
+        instruction::update_config(
+            &forge.program_id,
+            &authority,
+            ConfigParam::Difficulty(MAX_DIFFICULTY),
+        ),
+        instruction::update_config(
+            &forge.program_id,
+            &authority,
+            ConfigParam::CoalCost {
+                coal_per_ore: 0,
+                coal_base_cost: ONE,
+            },
+        ),
+    ];
+    process(&mut forge.context, &instructions, &[]).await;
+
+    let state = smelting_state(&mut forge).await;
+    assert_eq!(state.unsmelt_fee_bps, MAX_UNSMELT_FEE_BPS);
+    assert_eq!(state.difficulty, MAX_DIFFICULTY);
+    assert_eq!((state.coal_per_ore, state.coal_base_cost), (0, ONE));
+}
+
+#[tokio::test]
+async fn authority_transfers_only_to_the_proposed_key() {
+    let mut forge = setup().await;
+    let authority = forge.context.payer.pubkey();
+    let new_authority = Keypair::new();
+    let stranger = Keypair::new();
+
+    let propose =
+        instruction::propose_authority(&forge.program_id, &authority, &new_authority.pubkey());
+    process(&mut forge.context, std::slice::from_ref(&propose), &[]).await;
+    assert_eq!(
+        smelting_state(&mut forge).await.pending_authority,
+        new_authority.pubkey()
+    );
+
+    let instruction = instruction::accept_authority(&forge.program_id, &stranger.pubkey());
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&stranger],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+
+    // Proposing the default key cancels the transfer
+    let cancel = instruction::propose_authority(&forge.program_id, &authority, &Pubkey::default());
+    process(&mut forge.context, &[cancel], &[]).await;
+    let accept = instruction::accept_authority(&forge.program_id, &new_authority.pubkey());
+    assert_fails(
+        &mut forge.context,
+        std::slice::from_ref(&accept),
+        &[&new_authority],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+
+    process(&mut forge.context, &[propose], &[]).await;
+    process(&mut forge.context, &[accept], &[&new_authority]).await;
+
+    let state = smelting_state(&mut forge).await;
+    assert_eq!(state.authority, new_authority.pubkey());
+    assert_eq!(state.pending_authority, Pubkey::default());
+
+    // The previous authority lost its rights
+    let instruction =
+        instruction::update_config(&forge.program_id, &authority, ConfigParam::SuccessRate(50));
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn pausing_blocks_only_the_paused_operations() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    let authority = forge.context.payer.pubkey();
+    let owner = user.keypair.pubkey();
+    let state = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&state);
+    let smelt_instruction = instruction::smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &mints,
+        ONE,
+        mine(&state, &owner),
+        0,
+    );
+    let commit = instruction::commit_smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &mints,
+        ONE,
+        PendingSmelt::commitment_for(&[7; 32], &owner),
+    );
+    let unsmelt = instruction::unsmelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        0,
+    );
+
+    let instruction = instruction::set_pause(&forge.program_id, &authority, PAUSE_ALL + 1);
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[],
+        SmeltingError::InvalidConfig,
+    )
+    .await;
+
+    let cases = [
+        (PAUSE_UNSMELT, &unsmelt),
+        (PAUSE_SMELT, &smelt_instruction),
+        (PAUSE_SMELT, &commit),
+    ];
+    for (paused_operations, blocked) in cases {
+        let instruction = instruction::set_pause(&forge.program_id, &authority, paused_operations);
+        process(&mut forge.context, &[instruction], &[]).await;
+        assert_fails(
+            &mut forge.context,
+            std::slice::from_ref(blocked),
+            &[&user.keypair],
+            SmeltingError::Paused,
+        )
+        .await;
+    }
+
+    // Only smelting is paused now, unsmelting goes through again
+    process(&mut forge.context, &[unsmelt], &[&user.keypair]).await;
+
+    let instruction = instruction::set_pause(&forge.program_id, &authority, 0);
+    process(&mut forge.context, &[instruction], &[]).await;
+    process(&mut forge.context, &[smelt_instruction], &[&user.keypair]).await;
+
+    assert_eq!(
+        token_balance(&mut forge.context.banks_client, &user.ingot).await,
+        2 * ONE
+    );
+}
+
+#[tokio::test]
+async fn fees_are_withdrawn_only_to_the_treasury() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let treasury = create_user(&mut forge, 0, 0).await;
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    let authority = forge.context.payer.pubkey();
+    let mints = ForgeMints::from(&smelting_state(&mut forge).await);
+    let instructions = [
+        instruction::unsmelt(
+            &forge.program_id,
+            &user.keypair.pubkey(),
+            &user.ore,
+            &user.ingot,
+            &mints,
+            ONE,
+            0,
+        ),
+        instruction::update_config(
+            &forge.program_id,
+            &authority,
+            ConfigParam::Treasury(treasury.keypair.pubkey()),
+        ),
+    ];
+    process(&mut forge.context, &instructions, &[&user.keypair]).await;
+    let fees = smelting_state(&mut forge).await.total_fees_accrued;
+    assert!(fees > 0);
+
+    let failures = [
+        (
+            instruction::withdraw_fees(&forge.program_id, &authority, &user.ore, &mints, fees),
+            SmeltingError::InvalidTokenAccountOwner,
+        ),
+        (
+            instruction::withdraw_fees(
+                &forge.program_id,
+                &authority,
+                &treasury.ore,
+                &mints,
+                fees + 1,
+            ),
+            SmeltingError::InsufficientFees,
+        ),
+    ];
+    for (instruction, expected) in failures {
+        assert_fails(&mut forge.context, &[instruction], &[], expected).await;
+    }
+
+    // The treasury can collect on its own
+    let instruction = instruction::withdraw_fees(
+        &forge.program_id,
+        &treasury.keypair.pubkey(),
+        &treasury.ore,
+        &mints,
+        fees,
+    );
+    process(&mut forge.context, &[instruction], &[&treasury.keypair]).await;
+
+    let state = smelting_state(&mut forge).await;
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &treasury.ore).await, fees);
+    assert_eq!(token_balance(banks_client, &state.ore_vault).await, ONE);
+    assert_eq!(state.total_fees_accrued, 0);
+    assert_eq!(state.total_ore_locked, ONE);
+}
+
+#[tokio::test]
+async fn transfer_ore_and_ingot() {
+    let mut forge = setup().await;
+    let sender = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let receiver = create_user(&mut forge, 0, 0).await;
+    smelt(&mut forge, &sender, 4 * ONE).await;
+    let before = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&before);
+
+    let instructions = [
+        instruction::transfer_ore(
+            &forge.program_id,
+            &sender.ore,
+            &receiver.ore,
+            &sender.keypair.pubkey(),
+            &mints,
+            ONE,
+        ),
+        instruction::transfer_ingot(
+            &forge.program_id,
+            &sender.ingot,
+            &receiver.ingot,
+            &sender.keypair.pubkey(),
+            &mints,
+            3 * ONE,
+        ),
+    ];
+
+    process(&mut forge.context, &instructions, &[&sender.keypair]).await;
+
+    let after = smelting_state(&mut forge).await;
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &sender.ore).await, 5 * ONE);
+    assert_eq!(token_balance(banks_client, &receiver.ore).await, ONE);
+    assert_eq!(token_balance(banks_client, &sender.ingot).await, ONE);
+    assert_eq!(token_balance(banks_client, &receiver.ingot).await, 3 * ONE);
+
+    // Transfers move tokens without touching the forge's books
+    assert_eq!(after.total_ingots_minted, before.total_ingots_minted);
+    assert_eq!(after.total_ore_locked, before.total_ore_locked);
+}
+
+#[tokio::test]
+async fn smelt_with_transfer_fee_ore_locks_only_what_arrives() {
+    // 1% of every ORE transfer is withheld by the mint
+    let mut forge = setup_with_ore_transfer_fee(Some(100)).await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+
+    smelt(&mut forge, &user, 2 * ONE).await;
+
+    let state = smelting_state(&mut forge).await;
+    let received = 2 * ONE - 2 * ONE / 100;
+    assert_eq!(state.ore_token_program, spl_token_2022::id());
+    assert_eq!(state.ingot_token_program, spl_token_2022::id());
+    assert_eq!(state.coal_token_program, spl_token::id());
+
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ore).await, 8 * ONE);
+    assert_eq!(
+        token_balance(banks_client, &state.ore_vault).await,
+        received
+    );
+    assert_eq!(token_balance(banks_client, &user.ingot).await, received);
+    assert_eq!(state.total_ore_locked, received);
+    assert_eq!(state.total_ingots_minted, received);
+}
+
+#[tokio::test]
+async fn token_2022_ingot_carries_its_own_metadata() {
+    let mut forge = setup_with_ore_transfer_fee(Some(100)).await;
+    let payer = forge.context.payer.insecure_clone();
+    let mints = ForgeMints::from(&smelting_state(&mut forge).await);
+
+    for (name, uri) in [
+        ("Ingot", "https://example.com/ingot.json"),
+        ("Forged Ingot", ""),
+    ] {
+        let instruction = instruction::set_ingot_metadata(
+            &forge.program_id,
+            &payer.pubkey(),
+            &mints,
+            name.to_string(),
+            "INGOT".to_string(),
+            uri.to_string(),
+        );
+        process(&mut forge.context, &[instruction], &[]).await;
+
+        let mint = forge
+            .context
+            .banks_client
+            .get_account(forge.ingot_mint)
+            .await
+            .unwrap()
+            .unwrap();
+        let metadata = StateWithExtensions::<Mint>::unpack(&mint.data)
+            .unwrap()
+            .get_variable_len_extension::<TokenMetadata>()
+            .unwrap();
+        assert_eq!(metadata.name, name);
+        assert_eq!(metadata.symbol, "INGOT");
+        assert_eq!(metadata.uri, uri);
+        assert_eq!(metadata.mint, forge.ingot_mint);
+    }
+}
+
+#[tokio::test]
+async fn reveal_settles_a_commit_from_an_earlier_slot() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    fund(&mut forge, &user).await;
+    let mints = ForgeMints::from(&smelting_state(&mut forge).await);
+    let secret = [7; 32];
+    let pending = commit(&mut forge, &user, 2 * ONE, &secret).await;
+    assert_eq!(pending.ore_amount, 2 * ONE);
+
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ore).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.coal).await, 8 * ONE);
+
+    let instruction = reveal(&forge, &mints, &user, secret);
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&user.keypair],
+        SmeltingError::RevealTooEarly,
+    )
+    .await;
+
+    forge.context.warp_to_slot(pending.commit_slot + 2).unwrap();
+    let instruction = reveal(&forge, &mints, &user, [8; 32]);
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&user.keypair],
+        SmeltingError::InvalidReveal,
+    )
+    .await;
+
+    let mut instruction = reveal(&forge, &mints, &user, secret);
+    instruction.data = SmeltingInstruction::RevealSmelt {
+        secret,
+        min_ingot_out: 2 * ONE + 1,
+    }
+    .pack();
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&user.keypair],
+        SmeltingError::Slippage,
+    )
+    .await;
+
+    let instruction = reveal(&forge, &mints, &user, secret);
+    process(&mut forge.context, &[instruction], &[&user.keypair]).await;
+
+    assert!(!pending_smelt_exists(&mut forge, &user).await);
+    let state = smelting_state(&mut forge).await;
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ore).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.coal).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.ingot).await, 2 * ONE);
+    assert_eq!(state.total_ingots_minted, 2 * ONE);
+    assert_eq!(state.total_ore_locked, 2 * ONE);
+}
+
+#[tokio::test]
+async fn expired_commit_can_only_be_reclaimed() {
+    let mut forge = setup().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    fund(&mut forge, &user).await;
+    let mints = ForgeMints::from(&smelting_state(&mut forge).await);
+    let secret = [7; 32];
+    let pending = commit(&mut forge, &user, 2 * ONE, &secret).await;
+
+    let reclaim =
+        instruction::reclaim_smelt(&forge.program_id, &user.keypair.pubkey(), &user.ore, &mints);
+    assert_fails(
+        &mut forge.context,
+        std::slice::from_ref(&reclaim),
+        &[&user.keypair],
+        SmeltingError::CommitNotExpired,
+    )
+    .await;
+
+    forge
+        .context
+        .warp_to_slot(pending.commit_slot + SMELT_COMMIT_TIMEOUT_SLOTS + 1)
+        .unwrap();
+    let instruction = reveal(&forge, &mints, &user, secret);
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&user.keypair],
+        SmeltingError::CommitExpired,
+    )
+    .await;
+
+    process(&mut forge.context, &[reclaim], &[&user.keypair]).await;
+
+    // The ORE comes back, the COAL stays burned
+    assert!(!pending_smelt_exists(&mut forge, &user).await);
+    let state = smelting_state(&mut forge).await;
+    let banks_client = &mut forge.context.banks_client;
+    assert_eq!(token_balance(banks_client, &user.ore).await, 10 * ONE);
+    assert_eq!(token_balance(banks_client, &user.coal).await, 8 * ONE);
+    assert_eq!(token_balance(banks_client, &user.ingot).await, 0);
+    assert_eq!(state.total_ingots_minted, 0);
+}
+
+#[tokio::test]
+async fn migrate_rewrites_the_original_layout() {
+    let mut forge = setup().await;
+    let state = smelting_state(&mut forge).await;
+
+    // Swap in the 181-byte account the first deployment wrote
+    let mut data = vec![state.is_initialized];
+    data.extend_from_slice(state.authority.as_ref());
+    data.push(state.authority_bump);
+    for key in [
+        state.ore_mint,
+        state.ingot_mint,
+        state.coal_mint,
+        state.ore_vault,
+    ] {
+        data.extend_from_slice(key.as_ref());
+    }
+    data.extend_from_slice(&state.total_ingots_minted.to_le_bytes());
+    data.extend_from_slice(&state.total_ore_locked.to_le_bytes());
+    data.extend_from_slice(&[
+        state.ore_decimals,
+        state.ingot_decimals,
+        state.coal_decimals,
+    ]);
+    assert_eq!(data.len(), 181);
+    let rent = forge.context.banks_client.get_rent().await.unwrap();
+    let (state_address, _) = find_smelting_state_address(&forge.program_id);
+    let legacy = Account {
+        lamports: rent.minimum_balance(data.len()),
+        data,
+        owner: forge.program_id,
+        ..Account::default()
+    };
+    forge
+        .context
+        .set_account(&state_address, &AccountSharedData::from(legacy));
+
+    // Nothing else writes the old layout
+    let authority = forge.context.payer.pubkey();
+    let instruction =
+        instruction::update_config(&forge.program_id, &authority, ConfigParam::SuccessRate(50));
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[],
+        SmeltingError::StateNotMigrated,
+    )
+    .await;
+
+    let stranger = Keypair::new();
+    let instruction = instruction::migrate_state(&forge.program_id, &stranger.pubkey());
+    assert_fails(
+        &mut forge.context,
+        &[instruction],
+        &[&stranger],
+        SmeltingError::InvalidAuthority,
+    )
+    .await;
+
+    let instruction = instruction::migrate_state(&forge.program_id, &authority);
+    process(&mut forge.context, &[instruction], &[]).await;
+
+    let account = forge
+        .context
+        .banks_client
+        .get_account(state_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), SmeltingState::LEN);
+    assert_eq!(account.data[0], STATE_VERSION);
+    assert_eq!(account.lamports, rent.minimum_balance(SmeltingState::LEN));
+
+    // Stored fields survive, the rest take the defaults of the original program
+    let migrated = SmeltingState::unpack(&account.data).unwrap();
+    assert_eq!(migrated.authority, state.authority);
+    assert_eq!(migrated.ore_vault, state.ore_vault);
+    assert_eq!(migrated.ingot_mint, state.ingot_mint);
+    assert_eq!(migrated.coal_per_ore, ONE);
+    assert_eq!(migrated.ore_token_program, spl_token::id());
+    assert_eq!(migrated.challenge, [0; 32]);
+
+    let instruction =
+        instruction::update_config(&forge.program_id, &authority, ConfigParam::SuccessRate(50));
+    process(&mut forge.context, &[instruction], &[]).await;
+}
+
+// Natively run programs print events to stdout, only the SBF build logs them
+#[cfg(feature = "test-sbf")]
+#[tokio::test]
+async fn transaction_logs_decode_into_events() {
+    let mut forge = setup_sbf().await;
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let owner = user.keypair.pubkey();
+    let state = smelting_state(&mut forge).await;
+    let mints = ForgeMints::from(&state);
+
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &mints,
+        2 * ONE,
+        mine(&state, &owner),
+        0,
+    );
+    let events = process_events(&mut forge.context, &[instruction], &[&user.keypair]).await;
+    let coal_burned = 10 * ONE - token_balance(&mut forge.context.banks_client, &user.coal).await;
+    assert_eq!(
+        events,
+        [SmeltingEvent::SmeltSucceeded(SmeltSucceeded {
+            user: owner,
+            ore_amount: 2 * ONE,
+            ingot_amount: 2 * ONE,
+            coal_burned,
+        })]
+    );
+
+    let instruction = instruction::unsmelt(
+        &forge.program_id,
+        &owner,
+        &user.ore,
+        &user.ingot,
+        &mints,
+        ONE,
+        0,
+    );
+    let events = process_events(&mut forge.context, &[instruction], &[&user.keypair]).await;
+    let fee = state.calculate_unsmelt_fee(ONE).unwrap();
+    assert_eq!(
+        events,
+        [SmeltingEvent::Unsmelted(Unsmelted {
+            user: owner,
+            ingot_amount: ONE,
+            ore_returned: ONE - fee,
+            fee,
+        })]
+    );
+
+    let authority = forge.context.payer.pubkey();
+    let instruction =
+        instruction::update_config(&forge.program_id, &authority, ConfigParam::SuccessRate(50));
+    let events = process_events(&mut forge.context, &[instruction], &[]).await;
+    assert_eq!(
+        events,
+        [SmeltingEvent::ConfigChanged(ConfigChanged {
+            authority,
+            change: ConfigChange::Param(ConfigParam::SuccessRate(50)),
+        })]
+    );
+}
+
+// Initialize as the Pack baseline decodes it, from before Initialize read the program data
+#[cfg(feature = "test-sbf")]
+fn pack_baseline_initialize(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    ore_mint: &Pubkey,
+    coal_mint: &Pubkey,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    // Instruction version 1, `Initialize`, INGOT decimals and a 1:1 wrap ratio
+    let mut data = vec![1, 5, DECIMALS];
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.extend_from_slice(&1u64.to_le_bytes());
+    Instruction::new_with_bytes(
+        *program_id,
+        &data,
+        vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new_readonly(*ore_mint, false),
+            AccountMeta::new_readonly(*coal_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+// A PoW smelt as the Pack baseline decodes it, mined against its own challenge
+#[cfg(feature = "test-sbf")]
+async fn pack_baseline_smelt(
+    forge: &mut Forge,
+    program_id: &Pubkey,
+    user: &User,
+    ingot_account: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (state, _) = find_smelting_state_address(program_id);
+    let (forge_authority, _) = find_forge_authority_address(program_id);
+    let (ingot_mint, _) = find_ingot_mint_address(program_id);
+    let (ore_vault, _) = find_ore_vault_address(program_id);
+    // After the version byte: flag, authority, bump, four mints and the vault, two totals
+    // and three decimals, then difficulty and challenge
+    let stored = forge
+        .context
+        .banks_client
+        .get_account(state)
+        .await
+        .unwrap()
+        .unwrap()
+        .data;
+    let challenge_state = SmeltingState {
+        difficulty: stored[182],
+        challenge: stored[183..215].try_into().unwrap(),
+        ..SmeltingState::default()
+    };
+    let nonce = mine(&challenge_state, &user.keypair.pubkey());
+
+    // Instruction version 1, `Smelt`, amount and nonce
+    let mut data = vec![1, 0];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&nonce.to_le_bytes());
+    Instruction::new_with_bytes(
+        *program_id,
+        &data,
+        vec![
+            AccountMeta::new_readonly(user.keypair.pubkey(), true),
+            AccountMeta::new(user.ore, false),
+            AccountMeta::new(user.coal, false),
+            AccountMeta::new(*ingot_account, false),
+            AccountMeta::new(state, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(forge.coal_mint, false),
+            AccountMeta::new(ingot_mint, false),
+            AccountMeta::new(ore_vault, false),
+            AccountMeta::new_readonly(forge_authority, false),
+            AccountMeta::new_readonly(forge.ore_mint, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    )
+}
+
+#[cfg(feature = "test-sbf")]
+async fn compute_units(forge: &mut Forge, instruction: Instruction, user: &User) -> u64 {
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&forge.context.payer.pubkey()),
+        &[&forge.context.payer, &user.keypair],
+        forge.context.last_blockhash,
+    );
+    let simulation = forge
+        .context
+        .banks_client
+        .simulate_transaction(transaction)
+        .await
+        .unwrap();
+    assert!(simulation.result.unwrap().is_ok());
+    simulation.simulation_details.unwrap().units_consumed
+}
+
+// Native builtins are not metered, `cargo test-sbf` builds the program and runs this.
+// Smelts the same amount through the Pack baseline to show what reading state in place saves.
+#[cfg(feature = "test-sbf")]
+#[tokio::test]
+async fn smelt_compute_units() {
+    let program_id = Pubkey::new_unique();
+    let pack_program_id = Pubkey::new_unique();
+    let mut program_test = sbf_program_test(program_id);
+    program_test.add_program(PACK_BASELINE_PROGRAM, pack_program_id, None);
+    let mut forge = start(program_test, program_id, None).await;
+
+    let payer = forge.context.payer.pubkey();
+    let initialize =
+        pack_baseline_initialize(&pack_program_id, &payer, &forge.ore_mint, &forge.coal_mint);
+    process(&mut forge.context, &[initialize], &[]).await;
+
+    let user = create_user(&mut forge, 10 * ONE, 10 * ONE).await;
+    let (pack_ingot_mint, _) = find_ingot_mint_address(&pack_program_id);
+    let pack_ingot =
+        create_token_account(&mut forge.context, &pack_ingot_mint, &user.keypair.pubkey()).await;
+
+    let state = smelting_state(&mut forge).await;
+    let instruction = instruction::smelt(
+        &forge.program_id,
+        &user.keypair.pubkey(),
+        &user.ore,
+        &user.coal,
+        &user.ingot,
+        &ForgeMints::from(&state),
+        2 * ONE,
+        mine(&state, &user.keypair.pubkey()),
+        0,
+    );
+    let units = compute_units(&mut forge, instruction, &user).await;
+    let pack_instruction =
+        pack_baseline_smelt(&mut forge, &pack_program_id, &user, &pack_ingot, 2 * ONE).await;
+    let pack_units = compute_units(&mut forge, pack_instruction, &user).await;
+
+    assert!(
+        units < pack_units,
+        "smelt used {units} CU in place, {pack_units} CU through Pack"
+    );
+    assert!(
+        units <= SMELT_COMPUTE_UNITS,
+        "smelt used {units} CU, over the {SMELT_COMPUTE_UNITS} CU budget"
+    );
+}
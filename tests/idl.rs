@@ -0,0 +1,189 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anchor_lang_idl_spec::{
+    Idl, IdlDefinedFields, IdlInstruction, IdlInstructionAccount, IdlInstructionAccountItem,
+    IdlSeed, IdlType, IdlTypeDefTy,
+};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use theforgeonsolana::{
+    idl::{every_instruction, idl, idl_json},
+    instruction,
+};
+
+const CHECKED_IN: &str = include_str!("../idl/theforgeonsolana.json");
+
+#[test]
+fn checked_in_idl_is_up_to_date() {
+    assert!(
+        idl_json() == CHECKED_IN,
+        "idl/theforgeonsolana.json is stale, regenerate it with \
+         `cargo run --example idl --features idl > idl/theforgeonsolana.json`"
+    );
+}
+
+fn instruction_for<'a>(idl: &'a Idl, instruction: &Instruction) -> &'a IdlInstruction {
+    idl.instructions
+        .iter()
+        .find(|candidate| instruction.data.starts_with(&candidate.discriminator))
+        .expect("every instruction is in the IDL")
+}
+
+fn accounts(instruction: &IdlInstruction) -> Vec<&IdlInstructionAccount> {
+    instruction
+        .accounts
+        .iter()
+        .map(|item| match item {
+            IdlInstructionAccountItem::Single(account) => account,
+            IdlInstructionAccountItem::Composite(_) => panic!("no composite accounts"),
+        })
+        .collect()
+}
+
+// Compares the IDL account list with a builder's from `from` on, PDAs included
+fn assert_accounts_match(idl: &Idl, program_id: &Pubkey, instruction: &Instruction, from: usize) {
+    let idl_instruction = instruction_for(idl, instruction);
+    let accounts = accounts(idl_instruction);
+    let required = accounts.iter().filter(|account| !account.optional).count();
+    assert!(
+        (required..=accounts.len()).contains(&instruction.accounts.len()),
+        "{} takes {} accounts",
+        idl_instruction.name,
+        instruction.accounts.len()
+    );
+
+    let addresses = accounts
+        .iter()
+        .zip(&instruction.accounts)
+        .map(|(account, meta)| (account.name.as_str(), meta.pubkey))
+        .collect::<HashMap<_, _>>();
+    for (account, meta) in accounts.iter().zip(&instruction.accounts).skip(from) {
+        let name = format!("{}.{}", idl_instruction.name, account.name);
+        assert_eq!(account.signer, meta.is_signer, "{name} signer");
+        assert_eq!(account.writable, meta.is_writable, "{name} writable");
+        if let Some(address) = &account.address {
+            assert_eq!(*address, meta.pubkey.to_string(), "{name} address");
+        }
+        if let Some(pda) = &account.pda {
+            let seeds = pda
+                .seeds
+                .iter()
+                .map(|seed| match seed {
+                    IdlSeed::Const(seed) => seed.value.clone(),
+                    IdlSeed::Account(seed) => addresses[seed.path.as_str()].to_bytes().to_vec(),
+                    IdlSeed::Arg(_) => panic!("{name} has an argument seed"),
+                })
+                .collect::<Vec<_>>();
+            let seeds = seeds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let program = match &pda.program {
+                Some(IdlSeed::Const(program)) => {
+                    Pubkey::try_from(program.value.as_slice()).unwrap()
+                }
+                None => *program_id,
+                Some(_) => panic!("{name} has a dynamic PDA program"),
+            };
+            assert_eq!(
+                Pubkey::find_program_address(&seeds, &program).0,
+                meta.pubkey,
+                "{name} PDA"
+            );
+        }
+    }
+}
+
+#[test]
+fn accounts_match_the_instruction_builders() {
+    let idl = idl();
+    // Seeds on the program's own address only resolve for the declared id
+    let program_id = theforgeonsolana::ID;
+    let instructions = every_instruction(&program_id);
+    for instruction in &instructions {
+        assert_accounts_match(&idl, &program_id, instruction, 0);
+    }
+
+    let built = instructions
+        .iter()
+        .map(|instruction| instruction_for(&idl, instruction).name.as_str())
+        .collect::<BTreeSet<_>>();
+    let listed = idl
+        .instructions
+        .iter()
+        .map(|instruction| instruction.name.as_str())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(built, listed);
+}
+
+#[test]
+fn user_stats_accounts_are_listed_as_optional() {
+    let idl = idl();
+    let program_id = theforgeonsolana::ID;
+    for instruction in every_instruction(&program_id) {
+        let name = &instruction_for(&idl, &instruction).name;
+        if !["smelt", "unsmelt", "reveal_smelt"].contains(&name.as_str()) {
+            continue;
+        }
+        let (base, user) = (instruction.accounts.len(), instruction.accounts[0].pubkey);
+        let instruction = instruction::with_user_stats(instruction, &program_id, &user);
+
+        // `with_user_stats` also makes the paying user writable
+        assert_accounts_match(&idl, &program_id, &instruction, base);
+    }
+}
+
+fn defined_names(ty: &IdlType, names: &mut BTreeSet<String>) {
+    match ty {
+        IdlType::Defined { name, .. } => {
+            names.insert(name.clone());
+        }
+        IdlType::Option(inner) | IdlType::Vec(inner) | IdlType::Array(inner, _) => {
+            defined_names(inner, names)
+        }
+        _ => {}
+    }
+}
+
+fn fields_defined_names(fields: &Option<IdlDefinedFields>, names: &mut BTreeSet<String>) {
+    match fields {
+        Some(IdlDefinedFields::Named(fields)) => {
+            for field in fields {
+                defined_names(&field.ty, names);
+            }
+        }
+        Some(IdlDefinedFields::Tuple(types)) => {
+            for ty in types {
+                defined_names(ty, names);
+            }
+        }
+        None => {}
+    }
+}
+
+#[test]
+fn every_referenced_type_is_defined() {
+    let idl = idl();
+    let mut referenced = BTreeSet::new();
+    for instruction in &idl.instructions {
+        for arg in &instruction.args {
+            defined_names(&arg.ty, &mut referenced);
+        }
+    }
+    referenced.extend(idl.events.iter().map(|event| event.name.clone()));
+    referenced.extend(idl.accounts.iter().map(|account| account.name.clone()));
+    for type_def in &idl.types {
+        match &type_def.ty {
+            IdlTypeDefTy::Struct { fields } => fields_defined_names(fields, &mut referenced),
+            IdlTypeDefTy::Enum { variants } => {
+                for variant in variants {
+                    fields_defined_names(&variant.fields, &mut referenced);
+                }
+            }
+            IdlTypeDefTy::Type { alias } => defined_names(alias, &mut referenced),
+        }
+    }
+
+    let defined = idl
+        .types
+        .iter()
+        .map(|type_def| type_def.name.clone())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(referenced, defined);
+}
@@ -0,0 +1,82 @@
+use mpl_token_metadata::{
+    accounts::Metadata,
+    instructions::{
+        CreateMetadataAccountV3, CreateMetadataAccountV3InstructionArgs, UpdateMetadataAccountV2,
+        UpdateMetadataAccountV2InstructionArgs,
+    },
+    types::DataV2,
+};
+use solana_program::{pubkey::Pubkey, system_program};
+use theforgeonsolana::metaplex;
+
+const NAME: &str = "Ingot";
+const SYMBOL: &str = "INGOT";
+const URI: &str = "https://example.com/ingot.json";
+
+fn data_v2() -> DataV2 {
+    DataV2 {
+        name: NAME.to_owned(),
+        symbol: SYMBOL.to_owned(),
+        uri: URI.to_owned(),
+        seller_fee_basis_points: 0,
+        creators: None,
+        collection: None,
+        uses: None,
+    }
+}
+
+#[test]
+fn program_id_and_metadata_address_match_metaplex() {
+    let mint = Pubkey::new_unique();
+    assert_eq!(metaplex::ID, mpl_token_metadata::ID);
+    assert_eq!(
+        metaplex::find_metadata_address(&mint),
+        Metadata::find_pda(&mint)
+    );
+}
+
+#[test]
+fn create_metadata_matches_metaplex() {
+    let metadata = Pubkey::new_unique();
+    let mint = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+
+    let expected = CreateMetadataAccountV3 {
+        metadata,
+        mint,
+        mint_authority: authority,
+        payer,
+        update_authority: (authority, true),
+        system_program: system_program::id(),
+        rent: None,
+    }
+    .instruction(CreateMetadataAccountV3InstructionArgs {
+        data: data_v2(),
+        is_mutable: true,
+        collection_details: None,
+    });
+    let ours = metaplex::create_metadata_account_v3(
+        &metadata, &mint, &authority, &payer, &authority, NAME, SYMBOL, URI,
+    );
+    assert_eq!(ours, expected);
+}
+
+#[test]
+fn update_metadata_matches_metaplex() {
+    let metadata = Pubkey::new_unique();
+    let authority = Pubkey::new_unique();
+
+    let expected = UpdateMetadataAccountV2 {
+        metadata,
+        update_authority: authority,
+    }
+    .instruction(UpdateMetadataAccountV2InstructionArgs {
+        data: Some(data_v2()),
+        new_update_authority: None,
+        primary_sale_happened: None,
+        is_mutable: None,
+    });
+    let ours = metaplex::update_metadata_account_v2(&metadata, &authority, NAME, SYMBOL, URI);
+    assert_eq!(ours, expected);
+}
@@ -0,0 +1,356 @@
+use proptest::prelude::*;
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use theforgeonsolana::{
+    constants::{MAX_UNSMELT_FEE_BPS, MIN_DIFFICULTY},
+    error::SmeltingError,
+    state::{PendingSmelt, SmeltingState, UserStats, STATE_VERSION},
+};
+
+#[derive(Debug, Clone)]
+enum Op {
+    Smelt(u64),
+    // Fraction of the outstanding INGOT to unsmelt, in basis points
+    Unsmelt(u16),
+}
+
+fn arb_pubkey() -> impl Strategy<Value = Pubkey> {
+    any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+}
+
+// A forge that could have come out of Initialize and UpdateConfig: decimals
+// are those real mints use and the fee stays within its cap
+fn arb_forge() -> impl Strategy<Value = SmeltingState> {
+    (
+        0u8..=9,
+        0u8..=9,
+        1u64..=1_000,
+        1u64..=1_000,
+        0u16..=MAX_UNSMELT_FEE_BPS,
+    )
+        .prop_map(
+            |(ore_decimals, ingot_decimals, numerator, denominator, unsmelt_fee_bps)| {
+                SmeltingState {
+                    is_initialized: 1,
+                    ore_decimals,
+                    ingot_decimals,
+                    wrap_ratio_numerator: numerator,
+                    wrap_ratio_denominator: denominator,
+                    unsmelt_fee_bps,
+                    max_ingot_supply: u64::MAX / 2,
+                    ..SmeltingState::default()
+                }
+            },
+        )
+}
+
+fn arb_op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (1u64..=1_000_000_000_000).prop_map(Op::Smelt),
+        (1u16..=10_000).prop_map(Op::Unsmelt),
+    ]
+}
+
+fn arb_smelting_state() -> impl Strategy<Value = SmeltingState> {
+    (
+        (
+            any::<u8>(),
+            arb_pubkey(),
+            any::<u8>(),
+            arb_pubkey(),
+            arb_pubkey(),
+            arb_pubkey(),
+            arb_pubkey(),
+            any::<u64>(),
+            any::<u64>(),
+        ),
+        (
+            any::<[u8; 4]>(),
+            any::<[u8; 32]>(),
+            any::<[u64; 4]>(),
+            arb_pubkey(),
+            any::<u8>(),
+            any::<u16>(),
+        ),
+        (
+            any::<u64>(),
+            any::<u64>(),
+            any::<u8>(),
+            any::<u64>(),
+            arb_pubkey(),
+        ),
+        (arb_pubkey(), arb_pubkey(), arb_pubkey()),
+    )
+        .prop_map(
+            |(
+                (
+                    is_initialized,
+                    authority,
+                    authority_bump,
+                    ore_mint,
+                    ingot_mint,
+                    coal_mint,
+                    ore_vault,
+                    total_ingots_minted,
+                    total_ore_locked,
+                ),
+                (
+                    [ore_decimals, ingot_decimals, coal_decimals, difficulty],
+                    challenge,
+                    [wrap_ratio_numerator, wrap_ratio_denominator, coal_per_ore, coal_base_cost],
+                    pending_authority,
+                    success_rate,
+                    unsmelt_fee_bps,
+                ),
+                (max_ingot_supply, max_amount, paused_operations, total_fees_accrued, treasury),
+                (ore_token_program, coal_token_program, ingot_token_program),
+            )| SmeltingState {
+                is_initialized,
+                authority,
+                authority_bump,
+                ore_mint,
+                ingot_mint,
+                coal_mint,
+                ore_vault,
+                total_ingots_minted,
+                total_ore_locked,
+                ore_decimals,
+                ingot_decimals,
+                coal_decimals,
+                difficulty,
+                challenge,
+                wrap_ratio_numerator,
+                wrap_ratio_denominator,
+                coal_per_ore,
+                coal_base_cost,
+                pending_authority,
+                success_rate,
+                unsmelt_fee_bps,
+                max_ingot_supply,
+                max_amount,
+                paused_operations,
+                total_fees_accrued,
+                treasury,
+                ore_token_program,
+                coal_token_program,
+                ingot_token_program,
+                ..SmeltingState::default()
+            },
+        )
+}
+
+fn arb_pending_smelt() -> impl Strategy<Value = PendingSmelt> {
+    (
+        any::<bool>(),
+        arb_pubkey(),
+        any::<u64>(),
+        any::<u64>(),
+        any::<[u8; 32]>(),
+        any::<u64>(),
+        any::<[u8; 3]>(),
+    )
+        .prop_map(
+            |(
+                is_initialized,
+                user,
+                ore_amount,
+                coal_amount,
+                commitment,
+                commit_slot,
+                [bump, ore_escrow_bump, coal_escrow_bump],
+            )| PendingSmelt {
+                is_initialized,
+                user,
+                ore_amount,
+                coal_amount,
+                commitment,
+                commit_slot,
+                bump,
+                ore_escrow_bump,
+                coal_escrow_bump,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn conversions_never_panic(
+        ore_decimals in any::<u8>(),
+        ingot_decimals in any::<u8>(),
+        numerator in any::<u64>(),
+        denominator in any::<u64>(),
+        amount in any::<u64>(),
+    ) {
+        let state = SmeltingState {
+            ore_decimals,
+            ingot_decimals,
+            wrap_ratio_numerator: numerator,
+            wrap_ratio_denominator: denominator,
+            ..SmeltingState::default()
+        };
+        let _ = state.ore_to_ingot(amount);
+        let _ = state.ingot_to_ore(amount);
+        let _ = state.coal_cost(amount);
+    }
+
+    #[test]
+    fn smelt_takes_no_more_ore_than_offered(forge in arb_forge(), ore_amount in any::<u64>()) {
+        // Amounts whose INGOT does not fit a u64 are rejected, not wrapped
+        let Ok((ingot_amount, ore_used)) = forge.ore_to_ingot(ore_amount) else {
+            return Ok(());
+        };
+        prop_assert!(ore_used <= ore_amount);
+        // Unsmelting straight away can never hand back more ORE than went in
+        prop_assert!(forge.ingot_to_ore(ingot_amount).unwrap() <= ore_used);
+    }
+
+    #[test]
+    fn fee_never_exceeds_amount(
+        unsmelt_fee_bps in 0u16..=10_000,
+        amount in any::<u64>(),
+    ) {
+        let state = SmeltingState { unsmelt_fee_bps, ..SmeltingState::default() };
+        prop_assert!(state.calculate_unsmelt_fee(amount).unwrap() <= amount);
+    }
+
+    // Smelting is the only way INGOT gets minted, so no sequence can leave it unbacked
+    #[test]
+    fn ore_locked_always_backs_ingots(
+        mut forge in arb_forge(),
+        ops in prop::collection::vec(arb_op(), 1..64),
+    ) {
+        let mut vault_balance = 0u64;
+
+        for op in ops {
+            match op {
+                Op::Smelt(ore_amount) => {
+                    let Ok((ingot_amount, ore_used)) = forge.ore_to_ingot(ore_amount) else {
+                        continue;
+                    };
+                    if ingot_amount == 0 || !forge.can_mint_ingot(ingot_amount).unwrap_or(false) {
+                        continue;
+                    }
+                    forge.update_on_successful_smelt(ore_used, ingot_amount).unwrap();
+                    vault_balance += ore_used;
+                }
+                Op::Unsmelt(fraction_bps) => {
+                    let ingot_amount =
+                        (forge.total_ingots_minted as u128 * fraction_bps as u128 / 10_000) as u64;
+                    let ore_amount = forge.ingot_to_ore(ingot_amount).unwrap();
+                    if ore_amount == 0 {
+                        continue;
+                    }
+                    let fee = forge.calculate_unsmelt_fee(ore_amount).unwrap();
+                    prop_assert!(fee <= ore_amount);
+                    prop_assert!(ore_amount <= forge.total_ore_locked);
+                    forge.update_on_unsmelt(ingot_amount, ore_amount, fee).unwrap();
+                    vault_balance -= ore_amount - fee;
+                }
+            }
+
+            prop_assert!(
+                forge.ingot_to_ore(forge.total_ingots_minted).unwrap() <= forge.total_ore_locked
+            );
+            prop_assert_eq!(vault_balance, forge.total_ore_locked + forge.total_fees_accrued);
+        }
+    }
+
+    #[test]
+    fn accounting_overflows_instead_of_clamping(total in any::<u64>(), amount in any::<u64>()) {
+        let mut state = SmeltingState {
+            total_ingots_minted: total,
+            total_ore_locked: total,
+            max_ingot_supply: u64::MAX,
+            ..SmeltingState::default()
+        };
+        let overflow = Err(SmeltingError::MathOverflow.into());
+
+        match total.checked_add(amount) {
+            Some(sum) => {
+                state.update_on_successful_smelt(amount, amount).unwrap();
+                prop_assert_eq!(state.total_ingots_minted, sum);
+                prop_assert_eq!(state.total_ore_locked, sum);
+                state.update_on_unsmelt(sum, sum, 0).unwrap();
+                prop_assert_eq!(state.update_on_unsmelt(1, 1, 0), overflow);
+            }
+            None => {
+                prop_assert_eq!(state.can_mint_ingot(amount), Err(SmeltingError::MathOverflow.into()));
+                prop_assert_eq!(state.update_on_successful_smelt(amount, amount), overflow);
+                prop_assert_eq!(state.total_ingots_minted, total);
+            }
+        }
+    }
+
+    #[test]
+    fn user_stats_overflow_instead_of_clamping(total in any::<u64>(), amount in any::<u64>()) {
+        let mut stats = UserStats {
+            coal_burned: total,
+            ore_locked: total,
+            ingots_minted: total,
+            ingots_unsmelted: total,
+            ..UserStats::default()
+        };
+        let overflow = Err(SmeltingError::MathOverflow.into());
+
+        match total.checked_add(amount) {
+            Some(sum) => {
+                stats.record_smelt(amount, amount, amount).unwrap();
+                prop_assert_eq!(stats.ore_locked, sum);
+                stats.record_unsmelt(0, u64::MAX).unwrap();
+                prop_assert_eq!(stats.ore_locked, 0);
+            }
+            None => {
+                prop_assert_eq!(stats.record_smelt(amount, amount, amount), overflow.clone());
+                prop_assert_eq!(stats.record_failed_smelt(amount), overflow.clone());
+                prop_assert_eq!(stats.record_unsmelt(amount, 0), overflow);
+            }
+        }
+    }
+
+    #[test]
+    fn smelting_state_pack_round_trip(state in arb_smelting_state()) {
+        let mut data = vec![0u8; SmeltingState::LEN];
+        SmeltingState::pack_into_slice(&state, &mut data);
+        prop_assert_eq!(SmeltingState::layout_version(&data).unwrap(), STATE_VERSION);
+        prop_assert_eq!(SmeltingState::unpack_from_slice(&data).unwrap(), state);
+    }
+
+    #[test]
+    fn original_layout_still_unpacks(
+        authority in arb_pubkey(),
+        total_ore_locked in any::<u64>(),
+        ore_decimals in 0u8..=9,
+    ) {
+        // The 181-byte layout: flag, authority and bump, mints and vault, totals, decimals
+        let mut data = vec![1];
+        data.extend_from_slice(authority.as_ref());
+        data.push(254);
+        data.extend_from_slice(&[7; 4 * 32]);
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&total_ore_locked.to_le_bytes());
+        data.extend_from_slice(&[ore_decimals, 6, 9]);
+        prop_assert_eq!(SmeltingState::layout_version(&data).unwrap(), 0);
+
+        // Stored fields survive, the rest fall back to how the forge behaved back then
+        let state = SmeltingState::unpack(&data).unwrap();
+        prop_assert_eq!(state.authority, authority);
+        prop_assert_eq!(state.authority_bump, 254);
+        prop_assert_eq!(state.total_ore_locked, total_ore_locked);
+        prop_assert_eq!(state.coal_decimals, 9);
+        prop_assert_eq!(state.difficulty, MIN_DIFFICULTY);
+        prop_assert_eq!(state.coal_per_ore, 10u64.pow(ore_decimals as u32));
+        prop_assert_eq!(state.ore_token_program, spl_token::id());
+
+        // Repacking moves it to the current layout
+        let mut packed = vec![0u8; SmeltingState::LEN];
+        SmeltingState::pack_into_slice(&state, &mut packed);
+        prop_assert_eq!(SmeltingState::layout_version(&packed).unwrap(), STATE_VERSION);
+        prop_assert_eq!(SmeltingState::unpack(&packed).unwrap(), state);
+    }
+
+    #[test]
+    fn pending_smelt_pack_round_trip(pending in arb_pending_smelt()) {
+        let mut data = vec![0u8; PendingSmelt::LEN];
+        PendingSmelt::pack_into_slice(&pending, &mut data);
+        prop_assert_eq!(PendingSmelt::unpack_from_slice(&data).unwrap(), pending);
+    }
+}